@@ -10,6 +10,23 @@ detect_init_system() {
     fi
 }
 
+USER_DEVICES=~/.local/share/razercontrol/laptops.json
+
+# Whether the user device list is a copy of a packaged one, as older versions
+# of this script installed it there. Those copies now shadow /usr/share.
+is_packaged_copy() {
+    [ -f "$USER_DEVICES" ] || return 1
+    cmp -s "$USER_DEVICES" data/devices/laptops.json && return 0
+    git rev-parse --git-dir 1>/dev/null 2>/dev/null || return 1
+    local sum=$(git hash-object "$USER_DEVICES")
+    for commit in $(git rev-list HEAD -- data/devices/laptops.json); do
+        if [ "$(git rev-parse "$commit:./data/devices/laptops.json" 2>/dev/null)" = "$sum" ]; then
+            return 0
+        fi
+    done
+    return 1
+}
+
 install() {
     echo "Building the project..."
     cargo build --release # TODO: The GUI should be optional. At least for now. Before releasing this, it sould be turned into a feature with an explicit cli switch to install it
@@ -39,7 +56,21 @@ install() {
     cp target/release/razer-cli ~/.local/bin/
     cp target/release/razer-settings ~/.local/bin/
     cp target/release/daemon ~/.local/share/razercontrol/
-    cp data/devices/laptops.json ~/.local/share/razercontrol/
+
+    # The packaged device list goes to /usr/share, ~/.local/share/razercontrol/laptops.json
+    # and laptops.d/ are left to local additions
+    if sudo mkdir -p /usr/share/razercontrol 2>/dev/null && sudo cp data/devices/laptops.json /usr/share/razercontrol/ 2>/dev/null; then
+        echo "✓ Installed the device list"
+        if is_packaged_copy; then
+            mv "$USER_DEVICES" "$USER_DEVICES.old"
+            echo "✓ Moved the device list left by an older install to $USER_DEVICES.old"
+        elif [ -f "$USER_DEVICES" ]; then
+            echo "⚠ $USER_DEVICES overrides entries of the packaged device list"
+        fi
+    else
+        echo "⚠ Could not install the device list to /usr/share (immutable OS?) - using the user file instead"
+        cp data/devices/laptops.json ~/.local/share/razercontrol/
+    fi
     
     # Install desktop file to user directory
     if [ -d ~/.local/share/applications ]; then
@@ -101,8 +132,14 @@ uninstall() {
     rm -f ~/.local/bin/razer-settings
     rm -f ~/.local/share/applications/razer-settings.desktop
     rm -f ~/.local/share/razercontrol/daemon
-    rm -f ~/.local/share/razercontrol/laptops.json
-    rmdir ~/.local/share/razercontrol 2>/dev/null || true
+    rm -f "$USER_DEVICES.old"
+    # Local device additions (laptops.json and laptops.d/) are kept, unless
+    # the file is just a copy of the packaged list
+    if is_packaged_copy; then
+        rm -f "$USER_DEVICES"
+    fi
+    rmdir ~/.local/share/razercontrol 2>/dev/null || echo "⚠ Kept the local device list in ~/.local/share/razercontrol"
+    sudo rm -rf /usr/share/razercontrol 2>/dev/null || true
     
     # Try to remove udev rules (may fail on immutable OS)
     if sudo rm -f /etc/udev/rules.d/99-hidraw-permissions.rules 2>/dev/null; then
@@ -5,6 +5,9 @@ use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
 #[derive(Parser)]
 #[command(version="0.5.0", about="razer laptop configuration for linux", name="razer-cli")]
 struct Cli {
+    /// daemon socket, instead of $RAZER_LAPTOP_CONTROL_SOCKET or the one in $XDG_RUNTIME_DIR
+    #[arg(long, global = true)]
+    socket: Option<String>,
     #[command(subcommand)]
     args: Args,
 }
@@ -21,6 +24,31 @@ enum Args {
         #[command(subcommand)]
         attr: WriteAttr,
     },
+    /// Print the changes the daemon reports (AC, settings, battery...) until interrupted
+    Monitor,
+    /// Work with device database files (laptops.json), no daemon needed
+    Devices {
+        #[command(subcommand)]
+        action: DevicesAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum DevicesAction {
+    /// Report invalid entries, duplicate PIDs and unknown features
+    Check {
+        /// the laptops.json to check
+        file: String,
+    },
+    /// Print the udev rule that opens up the hidraw nodes of the known models
+    Udev {
+        /// the laptops.json to read, the whole device database by default
+        #[arg(long)]
+        file: Option<String>,
+        /// compare the database with this rules file instead
+        #[arg(long)]
+        check: Option<String>,
+    },
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -38,11 +66,15 @@ impl OnOff {
 #[derive(Subcommand)]
 enum ReadAttr {
     /// Read the current fan speed
-    Fan(AcStateParam),
+    Fan(FanReadParams),
     /// Read the current power mode
     Power(AcStateParam),
     /// Read the current bho mode
     Bho,
+    /// Read the features of the device, as checked on the EC
+    Features,
+    /// Read the model and firmware details of the laptop
+    Info,
 }
 
 #[derive(Subcommand)]
@@ -73,6 +105,26 @@ struct FanParams {
     ac_state: AcState,
     /// fan speed in RPM
     speed: i32,
+    /// only set this fan (models with independent fans)
+    #[arg(long)]
+    zone: Option<FanZone>,
+}
+
+#[derive(Parser)]
+struct FanReadParams {
+    /// battery/plugged in
+    ac_state: AcState,
+    /// only read this fan (models with independent fans)
+    #[arg(long)]
+    zone: Option<FanZone>,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum FanZone {
+    /// CPU fan
+    Cpu,
+    /// GPU fan
+    Gpu,
 }
 
 
@@ -100,22 +152,35 @@ struct AcStateParam {
 
 
 fn main() {
-    if std::fs::metadata(comms::SOCKET_PATH).is_err() {
+    let cli = Cli::parse();
+    if let Some(path) = &cli.socket {
+        std::env::set_var(comms::SOCKET_ENV, path);
+    }
+
+    if !matches!(cli.args, Args::Devices { .. }) && std::fs::metadata(comms::socket_path()).is_err() {
         eprintln!("Error. Socket doesn't exit. Is daemon running?");
         std::process::exit(1);
     }
-
-    let cli = Cli::parse();
+    if !matches!(cli.args, Args::Devices { .. }) {
+        if let Err(msg) = comms::handshake("razer-cli") {
+            eprintln!("Error: {}", msg);
+            std::process::exit(1);
+        }
+    }
 
     match cli.args {
         Args::Read { attr } => match attr {
-            ReadAttr::Fan(AcStateParam { ac_state }) => read_fan_rpm(ac_state as usize),
+            ReadAttr::Fan(FanReadParams { ac_state, zone }) => {
+                read_fan_rpm(ac_state as usize, zone.map(|z| z as usize))
+            }
             ReadAttr::Power(AcStateParam { ac_state }) => read_power_mode(ac_state as usize),
             ReadAttr::Bho => read_bho(),
+            ReadAttr::Features => read_features(),
+            ReadAttr::Info => read_info(),
         },
         Args::Write { attr } => match attr {
-            WriteAttr::Fan(FanParams { ac_state, speed }) => {
-                write_fan_speed(ac_state as usize, speed)
+            WriteAttr::Fan(FanParams { ac_state, speed, zone }) => {
+                write_fan_speed(ac_state as usize, speed, zone.map(|z| z as usize))
             }
             WriteAttr::Power(PowerParams {
                 ac_state,
@@ -127,17 +192,198 @@ fn main() {
                 validate_and_write_bho(threshold, state)
             }
         },
+        Args::Monitor => monitor(),
+        Args::Devices { action } => match action {
+            DevicesAction::Check { file } => check_devices(&file),
+            DevicesAction::Udev { file, check: None } => print!("{}", udev_rules(&load_device_list(file.as_deref()))),
+            DevicesAction::Udev { file, check: Some(rules) } => check_udev_rules(&load_device_list(file.as_deref()), &rules),
+        },
+    }
+}
+
+/// Lints a device database, exits with 1 when there is anything to fix
+fn check_devices(file: &str) {
+    let json = match std::fs::read_to_string(file) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", file, e);
+            std::process::exit(1);
+        }
+    };
+    let (devices, errors) = match service::parse_devices(&json) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{} is not a list of devices: {}", file, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut problems = errors.len();
+    for error in &errors {
+        println!("Invalid {}", error);
+    }
+
+    // Entries may share a PID when `dmi` tells them apart
+    let mut seen: Vec<((u16, u16), &Option<String>, &str)> = vec![];
+    for device in &devices {
+        if let Some(ids) = device.ids() {
+            match seen.iter().find(|(other_ids, dmi, _)| *other_ids == ids && **dmi == device.dmi) {
+                Some((_, _, other)) => {
+                    problems += 1;
+                    println!("Duplicate {:04x}:{:04x}: {} and {}", ids.0, ids.1, other, device.name);
+                }
+                None => seen.push((ids, &device.dmi, &device.name)),
+            }
+        }
+        for feature in &device.features {
+            if !service::KNOWN_FEATURES.contains(&feature.as_str()) {
+                problems += 1;
+                println!("Unknown feature {:?} in {}", feature, device.name);
+            }
+        }
+    }
+
+    if problems > 0 {
+        println!("{}: {} problems", file, problems);
+        std::process::exit(1);
+    }
+    println!("{}: {} devices, no problems", file, devices.len());
+}
+
+/// Reads a single device file, or the layered device database
+fn load_device_list(file: Option<&str>) -> Vec<service::SupportedDevice> {
+    match file {
+        Some(file) => match service::load_devices(file) {
+            Ok((devices, errors)) => {
+                for error in errors {
+                    eprintln!("Skipping {}", error);
+                }
+                devices
+            },
+            Err(e) => {
+                eprintln!("Error reading {}: {}", file, e);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let database = service::DeviceDatabase::load();
+            for error in &database.errors {
+                eprintln!("Skipping {}", error);
+            }
+            database.devices
+        },
     }
 }
 
+/// Sorted VID/PID pairs of the devices, without duplicates
+fn device_ids(devices: &[service::SupportedDevice]) -> Vec<(u16, u16)> {
+    let mut ids: Vec<(u16, u16)> = devices.iter().filter_map(|device| device.ids()).collect();
+    ids.sort();
+    ids.dedup();
+    return ids;
+}
+
+/// One rule per vendor, matching all of its PIDs
+fn udev_rules(devices: &[service::SupportedDevice]) -> String {
+    let ids = device_ids(devices);
+    let mut vids: Vec<u16> = ids.iter().map(|(vid, _)| *vid).collect();
+    vids.dedup();
+
+    let mut rules = String::from("# Generated by `razer-cli devices udev` from laptops.json, do not edit\n");
+    for vid in vids {
+        let pids: Vec<String> = ids.iter()
+            .filter(|(v, _)| *v == vid)
+            .map(|(_, pid)| format!("{:04x}", pid))
+            .collect();
+        rules += &format!(
+            "KERNEL==\"hidraw*\", ATTRS{{idProduct}}==\"{}\", ATTRS{{idVendor}}==\"{:04x}\", MODE=\"0666\", TAG+=\"uaccess\"\n",
+            pids.join("|"), vid
+        );
+    }
+    return rules;
+}
+
+/// Value of `key=="..."` in a udev rule line
+fn udev_match<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!("{}==\"", key))? + key.len() + 3;
+    let end = line[start..].find('"')? + start;
+    return Some(&line[start..end]);
+}
+
+/// Reports the PIDs that are only in the database or only in the rules file,
+/// exits with 1 when they differ
+fn check_udev_rules(devices: &[service::SupportedDevice], file: &str) {
+    let rules = match std::fs::read_to_string(file) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", file, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut problems = 0;
+    let mut in_rules: Vec<(u16, u16)> = vec![];
+    for (n, line) in rules.lines().enumerate() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        let (vid, pids) = match (udev_match(line, "ATTRS{idVendor}"), udev_match(line, "ATTRS{idProduct}")) {
+            (Some(vid), Some(pids)) => (vid, pids),
+            _ => continue,
+        };
+        let vid = match u16::from_str_radix(vid, 16) {
+            Ok(vid) => vid,
+            Err(_) => {
+                problems += 1;
+                println!("Line {}: invalid vendor id {:?}", n + 1, vid);
+                continue;
+            }
+        };
+        for pid in pids.split('|') {
+            match u16::from_str_radix(pid, 16) {
+                Ok(pid) => in_rules.push((vid, pid)),
+                Err(_) => {
+                    problems += 1;
+                    println!("Line {}: invalid product id {:?}", n + 1, pid);
+                }
+            }
+        }
+    }
+    in_rules.sort();
+    in_rules.dedup();
+
+    let in_database = device_ids(devices);
+    for ids in &in_database {
+        if !in_rules.contains(ids) {
+            problems += 1;
+            let name = devices.iter().find(|d| d.ids() == Some(*ids)).map_or("", |d| d.name.as_str());
+            println!("Missing from {}: {:04x}:{:04x} {}", file, ids.0, ids.1, name);
+        }
+    }
+    for ids in &in_rules {
+        if !in_database.contains(ids) {
+            problems += 1;
+            println!("Not in the device database: {:04x}:{:04x}", ids.0, ids.1);
+        }
+    }
+
+    if problems > 0 {
+        println!("{}: {} problems, regenerate it with `razer-cli devices udev`", file, problems);
+        std::process::exit(1);
+    }
+    println!("{}: matches the {} devices of the database", file, in_database.len());
+}
+
 fn validate_and_write_bho(threshold: Option<u8>, state: OnOff) {
+    let limits = get_limits();
+    let (min, max) = limits.bho_threshold;
     match threshold {
         Some(threshold) => {
-            if !valid_bho_threshold(threshold) {
+            if !limits.valid_bho_threshold(threshold) {
                 Cli::command()
                     .error(
                         ErrorKind::InvalidValue,
-                        "Threshold must be multiple of 5 between 50 and 80",
+                        format!("Threshold must be multiple of {} between {} and {}", limits.bho_step, min, max),
                     )
                     .exit()
             }
@@ -152,35 +398,83 @@ fn validate_and_write_bho(threshold: Option<u8>, state: OnOff) {
                     )
                     .exit()
             }
-            write_bho(state.is_on(), 80)
+            write_bho(state.is_on(), max)
         }
     }
 }
 
 fn read_bho() {
-    send_data(comms::DaemonCommand::GetBatteryHealthOptimizer()).map_or_else(
-        || eprintln!("Unknown error occured when getting bho"),
-        |result| {
-            if let comms::DaemonResponse::GetBatteryHealthOptimizer { is_on, threshold } = result {
-                match is_on {
-                    true => {
-                        println!(
-                            "Battery health optimization is on with a threshold of {}",
-                            threshold
-                        );
-                    }
-                    false => {
-                        eprintln!("Battery health optimization is off");
-                    }
+    match send_data(comms::DaemonCommand::GetBatteryHealthOptimizer()) {
+        Ok(comms::DaemonResponse::GetBatteryHealthOptimizer { is_on, threshold }) => {
+            match is_on {
+                true => {
+                    println!(
+                        "Battery health optimization is on with a threshold of {}",
+                        threshold
+                    );
+                }
+                false => {
+                    eprintln!("Battery health optimization is off");
                 }
             }
-        },
-    );
+        }
+        Ok(_) => eprintln!("Daemon responded with invalid data!"),
+        Err(e) => exit_with(e),
+    }
+}
+
+fn read_features() {
+    match send_data(comms::DaemonCommand::GetFeatures) {
+        Ok(comms::DaemonResponse::GetFeatures { features, unsupported }) => {
+            println!("Features: {}", features.join(", "));
+            if !unsupported.is_empty() {
+                println!("Not supported by the EC: {}", unsupported.join(", "));
+            }
+        }
+        Ok(_) => eprintln!("Daemon responded with invalid data!"),
+        Err(e) => exit_with(e),
+    }
+}
+
+fn monitor() {
+    let mut socket = match comms::subscribe() {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error subscribing to the daemon events: {}", e);
+            std::process::exit(1);
+        }
+    };
+    loop {
+        match comms::read_event(&mut socket) {
+            Ok(event) => println!("{:?}", event),
+            Err(e) => {
+                eprintln!("Lost the connection to the daemon: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn read_info() {
+    match send_data(comms::DaemonCommand::GetDeviceInfo) {
+        Ok(comms::DaemonResponse::GetDeviceInfo { info }) => {
+            println!("Model: {}", info.name);
+            println!("USB ID: {:04x}:{:04x}", info.vid, info.pid);
+            println!("Product: {}", info.dmi.product_name);
+            println!("Product version: {}", info.dmi.product_version);
+            println!("Board: {}", info.dmi.board_name);
+            println!("BIOS version: {}", info.dmi.bios_version);
+            println!("EC firmware: {}", info.firmware.as_deref().unwrap_or("unknown"));
+            println!("Serial number: {}", info.serial.as_deref().unwrap_or("unknown"));
+        }
+        Ok(_) => eprintln!("Daemon responded with invalid data!"),
+        Err(e) => exit_with(e),
+    }
 }
 
 fn write_bho(on: bool, threshold: u8) {
     if !on {
-        bho_toggle_off();
+        bho_toggle_off(threshold);
         return;
     }
 
@@ -188,84 +482,83 @@ fn write_bho(on: bool, threshold: u8) {
 }
 
 fn bho_toggle_on(threshold: u8) {
-    if !valid_bho_threshold(threshold) {
-        eprintln!("Threshold value must be a multiple of five between 50 and 80");
-        return;
-    }
-
-    send_data(comms::DaemonCommand::SetBatteryHealthOptimizer {
+    match send_data(comms::DaemonCommand::SetBatteryHealthOptimizer {
         is_on: true,
         threshold: threshold,
-    })
-    .map_or_else(
-        || eprintln!("Unknown error occured when toggling bho"),
-        |result| {
-            if let comms::DaemonResponse::SetBatteryHealthOptimizer { result } = result {
-                match result {
-                    true => {
-                        println!(
-                            "Battery health optimization is on with a threshold of {}",
-                            threshold
-                        );
-                    }
-                    false => {
-                        eprintln!("Failed to turn on bho with threshold of {}", threshold);
-                    }
+    }) {
+        Ok(comms::DaemonResponse::SetBatteryHealthOptimizer { result }) => {
+            match result {
+                true => {
+                    println!(
+                        "Battery health optimization is on with a threshold of {}",
+                        threshold
+                    );
+                }
+                false => {
+                    eprintln!("Failed to turn on bho with threshold of {}", threshold);
                 }
             }
-        },
-    );
-}
-
-fn valid_bho_threshold(threshold: u8) -> bool {
-    if threshold % 5 != 0 {
-        return false;
-    }
-
-    if threshold < 50 || threshold > 80 {
-        return false;
+        }
+        Ok(_) => eprintln!("Daemon responded with invalid data!"),
+        Err(e) => exit_with(e),
     }
-
-    return true;
 }
 
-fn bho_toggle_off() {
-    send_data(comms::DaemonCommand::SetBatteryHealthOptimizer {
+/// The threshold is kept by the EC but ignored while off
+fn bho_toggle_off(threshold: u8) {
+    match send_data(comms::DaemonCommand::SetBatteryHealthOptimizer {
         is_on: false,
-        threshold: 80,
-    })
-    .map_or_else(
-        || eprintln!("Unknown error occured when toggling bho"),
-        |result| {
-            if let comms::DaemonResponse::SetBatteryHealthOptimizer { result } = result {
-                match result {
-                    true => {
-                        println!("Successfully turned off bho");
-                    }
-                    false => {
-                        eprintln!("Failed to turn off bho");
-                    }
+        threshold,
+    }) {
+        Ok(comms::DaemonResponse::SetBatteryHealthOptimizer { result }) => {
+            match result {
+                true => {
+                    println!("Successfully turned off bho");
+                }
+                false => {
+                    eprintln!("Failed to turn off bho");
                 }
             }
-        },
-    );
+        }
+        Ok(_) => eprintln!("Daemon responded with invalid data!"),
+        Err(e) => exit_with(e),
+    }
 }
 
 
 
-fn send_data(opt: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
-    match comms::bind() {
-        Some(socket) => comms::send_to_daemon(opt, socket),
-        None => {
-            eprintln!("Error. Cannot bind to socket");
-            None
-        },
+/// Limits of the attached laptop, the generic ones if the daemon can't tell
+fn get_limits() -> service::Limits {
+    match send_data(comms::DaemonCommand::GetLimits) {
+        Ok(comms::DaemonResponse::GetLimits { limits }) => limits,
+        _ => service::Limits::default(),
+    }
+}
+
+/// Sends a command, the error the daemon answered is up to the caller
+fn send_data(opt: comms::DaemonCommand) -> Result<comms::DaemonResponse, String> {
+    let socket = comms::bind().ok_or_else(|| "Cannot bind to socket".to_string())?;
+    match comms::send_to_daemon(opt, socket) {
+        Some(comms::DaemonResponse::Error { error }) => Err(error.to_string()),
+        Some(response) => Ok(response),
+        None => Err("No answer from the daemon".to_string()),
     }
 }
 
-fn read_fan_rpm(ac: usize) {
-    match send_data(comms::DaemonCommand::GetFanSpeed { ac }) {
-        Some(comms::DaemonResponse::GetFanSpeed { rpm }) => {
+/// Ends the CLI after a failed command
+fn exit_with(error: String) -> ! {
+    eprintln!("Error: {}", error);
+    std::process::exit(1);
+}
+
+fn read_fan_rpm(ac: usize, zone: Option<usize>) {
+    let command = match zone {
+        Some(zone) => comms::DaemonCommand::GetZoneFanSpeed { ac, zone },
+        None => comms::DaemonCommand::GetFanSpeed { ac },
+    };
+    match send_data(command) {
+        Ok(comms::DaemonResponse::GetFanSpeed { rpm })
+        | Ok(comms::DaemonResponse::GetZoneFanSpeed { rpm }) => {
             let rpm_desc: String = match rpm {
                 f if f < 0 => String::from("Unknown"),
                 0 => String::from("Auto (0)"),
@@ -273,59 +566,67 @@ fn read_fan_rpm(ac: usize) {
             };
             println!("Current fan setting: {}", rpm_desc);
         },
-        Some(_) => eprintln!("Daemon responded with invalid data!"),
-        None => eprintln!("Unknown daemon error!"),
+        Ok(_) => eprintln!("Daemon responded with invalid data!"),
+        Err(e) => exit_with(e),
     }
 }
 
 
 fn read_power_mode(ac: usize) {
-    if let Some(resp) = send_data(comms::DaemonCommand::GetPwrLevel { ac }) {
-        if let comms::DaemonResponse::GetPwrLevel { pwr } = resp {
-            let power_desc: &str = match pwr {
-                0 => "Balanced",
-                1 => "Gaming",
-                2 => "Creator",
-                3 => "Silent",
-                4 => "Custom",
-                _ => "Unknown",
-            };
-            println!("Current power setting: {}", power_desc);
-            if pwr == 4 {
-                if let Some(resp) = send_data(comms::DaemonCommand::GetCPUBoost { ac }) {
-                    if let comms::DaemonResponse::GetCPUBoost { cpu } = resp {
-                        let cpu_boost_desc: &str = match cpu {
-                            0 => "Low",
-                            1 => "Medium",
-                            2 => "High",
-                            3 => "Boost",
-                            _ => "Unknown",
-                        };
-                        println!("Current CPU setting: {}", cpu_boost_desc);
-                    };
-                }
-                if let Some(resp) = send_data(comms::DaemonCommand::GetGPUBoost { ac }) {
-                    if let comms::DaemonResponse::GetGPUBoost { gpu } = resp {
-                        let gpu_boost_desc: &str = match gpu {
-                            0 => "Low",
-                            1 => "Medium",
-                            2 => "High",
-                            _ => "Unknown",
-                        };
-                        println!("Current GPU setting: {}", gpu_boost_desc);
-                    };
-                }
-            }
-        } else {
+    let pwr = match send_data(comms::DaemonCommand::GetPwrLevel { ac }) {
+        Ok(comms::DaemonResponse::GetPwrLevel { pwr }) => pwr,
+        Ok(_) => {
             eprintln!("Daemon responded with invalid data!");
+            return;
+        }
+        Err(e) => exit_with(e),
+    };
+    let power_desc: &str = match pwr {
+        0 => "Balanced",
+        1 => "Gaming",
+        2 => "Creator",
+        3 => "Silent",
+        4 => "Custom",
+        _ => "Unknown",
+    };
+    println!("Current power setting: {}", power_desc);
+    if pwr == 4 {
+        // The power mode is known already, show it even without the boosts
+        match send_data(comms::DaemonCommand::GetCPUBoost { ac }) {
+            Ok(comms::DaemonResponse::GetCPUBoost { cpu }) => {
+                let cpu_boost_desc: &str = match cpu {
+                    0 => "Low",
+                    1 => "Medium",
+                    2 => "High",
+                    3 => "Boost",
+                    _ => "Unknown",
+                };
+                println!("Current CPU setting: {}", cpu_boost_desc);
+            },
+            Ok(_) => eprintln!("Daemon responded with invalid data!"),
+            Err(e) => eprintln!("Error reading the CPU boost: {}", e),
+        }
+        match send_data(comms::DaemonCommand::GetGPUBoost { ac }) {
+            Ok(comms::DaemonResponse::GetGPUBoost { gpu }) => {
+                let gpu_boost_desc: &str = match gpu {
+                    0 => "Low",
+                    1 => "Medium",
+                    2 => "High",
+                    _ => "Unknown",
+                };
+                println!("Current GPU setting: {}", gpu_boost_desc);
+            },
+            Ok(_) => eprintln!("Daemon responded with invalid data!"),
+            Err(e) => eprintln!("Error reading the GPU boost: {}", e),
         }
     }
 }
 
 fn write_pwr_mode(ac: usize, pwr_mode: u8, cpu_mode: Option<u8>, gpu_mode: Option<u8>) {
-    if pwr_mode > 4 {
+    let limits = get_limits();
+    if !limits.has_power_mode(pwr_mode) {
         Cli::command()
-            .error(ErrorKind::InvalidValue, "Power mode must be 0, 1, 2, 3 or 4")
+            .error(ErrorKind::InvalidValue, format!("Power mode must be one of {:?} on this laptop", limits.power_modes))
             .exit()
     }
 
@@ -335,9 +636,9 @@ fn write_pwr_mode(ac: usize, pwr_mode: u8, cpu_mode: Option<u8>, gpu_mode: Optio
         cpu_mode.unwrap_or(0)
     };
 
-    if cm > 3 {
+    if cm > limits.max_cpu_boost {
         Cli::command()
-            .error(ErrorKind::InvalidValue, "CPU mode must be between 0 and 3")
+            .error(ErrorKind::InvalidValue, format!("CPU mode must be between 0 and {}", limits.max_cpu_boost))
             .exit()
     }
 
@@ -347,9 +648,9 @@ fn write_pwr_mode(ac: usize, pwr_mode: u8, cpu_mode: Option<u8>, gpu_mode: Optio
         gpu_mode.unwrap_or(0)
     };
 
-    if gm > 2 {
+    if gm > limits.max_gpu_boost {
         Cli::command()
-            .error(ErrorKind::InvalidValue, "GPU mode must be between 0 and 2")
+            .error(ErrorKind::InvalidValue, format!("GPU mode must be between 0 and {}", limits.max_gpu_boost))
             .exit()
     }
 
@@ -359,25 +660,22 @@ fn write_pwr_mode(ac: usize, pwr_mode: u8, cpu_mode: Option<u8>, gpu_mode: Optio
         cpu: cm,
         gpu: gm,
     }) {
-        Some(_) => read_power_mode(ac),
-        None => {
-            Cli::command()
-                .error(
-                    ErrorKind::DisplayHelp,
-                    "An error occurred while sending the command to the daemon",
-                )
-                .exit()
-        },
+        Ok(_) => read_power_mode(ac),
+        Err(e) => exit_with(e),
     }
 }
 
 
 
 
-fn write_fan_speed(ac: usize, x: i32) {
-    match send_data(comms::DaemonCommand::SetFanSpeed { ac, rpm: x }) {
-        Some(_) => read_fan_rpm(ac),
-        None => eprintln!("Unknown error!"),
+fn write_fan_speed(ac: usize, x: i32, zone: Option<usize>) {
+    let command = match zone {
+        Some(zone) => comms::DaemonCommand::SetZoneFanSpeed { ac, zone, rpm: x },
+        None => comms::DaemonCommand::SetFanSpeed { ac, rpm: x },
+    };
+    match send_data(command) {
+        Ok(_) => read_fan_rpm(ac, zone),
+        Err(e) => exit_with(e),
     }
 }
 
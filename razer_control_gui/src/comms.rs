@@ -1,11 +1,80 @@
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::os::unix::net::{UnixListener, UnixStream};
 
-/// Razer laptop control socket path
-pub const SOCKET_PATH: &str = "/tmp/razercontrol-socket";
+/// Overrides the socket path, for the daemon and the clients
+pub const SOCKET_ENV: &str = "RAZER_LAPTOP_CONTROL_SOCKET";
+/// Socket file name in the runtime directory
+const SOCKET_NAME: &str = "razercontrol-socket";
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Razer laptop control socket path: `SOCKET_ENV`, or the socket in
+/// $XDG_RUNTIME_DIR (or /run/user/<uid> when a service manager didn't set it).
+/// Without either, a private directory in /tmp.
+pub fn socket_path() -> String {
+    if let Ok(path) = std::env::var(SOCKET_ENV) {
+        return path;
+    }
+    if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+        if !dir.is_empty() {
+            return format!("{}/{}", dir, SOCKET_NAME);
+        }
+    }
+    let uid = current_uid();
+    let run_dir = format!("/run/user/{}", uid);
+    if std::path::Path::new(&run_dir).is_dir() {
+        return format!("{}/{}", run_dir, SOCKET_NAME);
+    }
+    return format!("{}/{}", private_dir(uid), SOCKET_NAME);
+}
+
+/// Directory of the socket when there is no runtime directory
+fn private_dir(uid: u32) -> String {
+    return format!("/tmp/razercontrol-{}", uid);
+}
+
+/// Whether `dir` is a directory (not a symlink) only `uid` can use. /tmp is
+/// shared, anybody could have created the directory before us.
+fn is_private_dir(dir: &std::path::Path, uid: u32) -> bool {
+    match std::fs::symlink_metadata(dir) {
+        Ok(metadata) => {
+            return metadata.file_type().is_dir() && metadata.uid() == uid && metadata.mode() & 0o777 == 0o700;
+        },
+        Err(_) => return false,
+    }
+}
+
+/// Socket of the JSON lines protocol, next to the bincode one: one
+/// `DaemonCommand` per line in, one `DaemonResponse` per line out
+#[allow(dead_code)]
+pub fn json_socket_path() -> String {
+    return format!("{}.json", socket_path());
+}
+
+fn current_uid() -> u32 {
+    // getuid can't fail
+    return unsafe { libc::getuid() };
+}
+
+/// Largest payload either side accepts in a frame
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Version of the socket protocol. bincode encodes enum variants by index, so
+/// commands, responses and errors are only ever appended, which keeps older
+/// clients working. Anything else (reordering, removing, changing fields)
+/// bumps it.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Commands the daemon answers, sent back by `Hello`. Keep in the order of
+/// `DaemonCommand`.
+pub const COMMANDS: [&str; 18] = [
+    "SetFanSpeed", "GetFanSpeed", "SetPowerMode", "GetPwrLevel", "GetCPUBoost",
+    "GetGPUBoost", "SetIdle", "SetBatteryHealthOptimizer", "GetBatteryHealthOptimizer",
+    "GetDeviceName", "SetZoneFanSpeed", "GetZoneFanSpeed", "GetFeatures", "GetLimits",
+    "GetDeviceInfo", "Hello", "Subscribe", "Request",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 /// Represents data sent TO the daemon
 pub enum DaemonCommand {
     SetFanSpeed { ac: usize, rpm: i32 },      // Fan speed
@@ -17,10 +86,41 @@ pub enum DaemonCommand {
     SetIdle {ac: usize, val: u32 },
     SetBatteryHealthOptimizer { is_on: bool, threshold: u8 },
     GetBatteryHealthOptimizer (),
-    GetDeviceName 
+    GetDeviceName,
+    SetZoneFanSpeed { ac: usize, zone: usize, rpm: i32 }, // zone 0 is the CPU fan, 1 the GPU one
+    GetZoneFanSpeed { ac: usize, zone: usize },
+    GetFeatures,
+    GetLimits,
+    GetDeviceInfo,
+    /// First request of a client, see `handshake`
+    Hello { client: String, protocol: u32 },
+    /// Keeps the connection open, the daemon answers `Subscribed` and then
+    /// sends an `Event` for every change
+    Subscribe,
+    /// A command with an id, answered with a `Reply` carrying the same id.
+    /// Lets a client keep the connection open for several commands, see
+    /// `Connection`.
+    Request { id: u32, command: Box<DaemonCommand> },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl DaemonCommand {
+    /// Whether the command leaves the settings alone, for the access policy
+    /// of the daemon
+    #[allow(dead_code)]
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            DaemonCommand::SetFanSpeed { .. } | DaemonCommand::SetPowerMode { .. } | DaemonCommand::SetIdle { .. }
+            | DaemonCommand::SetBatteryHealthOptimizer { .. } | DaemonCommand::SetZoneFanSpeed { .. } => false,
+            DaemonCommand::GetFanSpeed { .. } | DaemonCommand::GetPwrLevel { .. } | DaemonCommand::GetCPUBoost { .. }
+            | DaemonCommand::GetGPUBoost { .. } | DaemonCommand::GetBatteryHealthOptimizer() | DaemonCommand::GetDeviceName
+            | DaemonCommand::GetZoneFanSpeed { .. } | DaemonCommand::GetFeatures | DaemonCommand::GetLimits
+            | DaemonCommand::GetDeviceInfo | DaemonCommand::Hello { .. } | DaemonCommand::Subscribe => true,
+            DaemonCommand::Request { command, .. } => command.is_read_only(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 /// Represents data sent back from Daemon after it receives
 /// a command.
 pub enum DaemonResponse {
@@ -33,12 +133,89 @@ pub enum DaemonResponse {
     SetIdle { result: bool },
     SetBatteryHealthOptimizer { result: bool },
     GetBatteryHealthOptimizer { is_on: bool, threshold: u8 },
-    GetDeviceName { name: String }
+    GetDeviceName { name: String },
+    Error { error: DeviceError },
+    SetZoneFanSpeed { result: bool },
+    GetZoneFanSpeed { rpm: i32 },
+    /// Features in use after probing the EC, and the declared ones it rejected
+    GetFeatures { features: Vec<String>, unsupported: Vec<String> },
+    /// Ranges the attached model accepts
+    GetLimits { limits: service::Limits },
+    /// The model in use and the DMI strings of the machine
+    GetDeviceInfo { info: service::DeviceInfo },
+    Hello { daemon_version: String, protocol: u32, commands: Vec<String> },
+    Subscribed,
+    Event { event: DaemonEvent },
+    Reply { id: u32, response: Box<DaemonResponse> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Something that changed in the daemon, pushed to the subscribed clients
+pub enum DaemonEvent {
+    /// The AC adapter was plugged in or out, the config of `online as usize` is now in use
+    AcState { online: bool },
+    PowerMode { ac: usize, pwr: u8, cpu: u8, gpu: u8 },
+    FanSpeed { ac: usize, rpm: i32 },
+    ZoneFanSpeed { ac: usize, zone: usize, rpm: i32 },
+    BatteryHealthOptimizer { is_on: bool, threshold: u8 },
+    /// Idle timeout in minutes
+    Idle { ac: usize, timeout: u32 },
+    /// The screen got blanked (lights off) or came back
+    Screensaver { active: bool },
+    Battery { percentage: f64 },
+    /// The machine is about to suspend (true) or resumed (false)
+    Sleep { start: bool },
+    DeviceAttached { name: String },
+    DeviceDetached,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Why the daemon could not carry out a command
+pub enum DeviceError {
+    /// The HID transport failed (device gone, I/O error...)
+    Transport(String),
+    /// The answer doesn't belong to the request or is malformed
+    ResponseMismatch,
+    /// The EC was still busy after all the retries
+    Busy,
+    /// The EC reported a failure
+    Failure,
+    /// The EC reported a timeout
+    Timeout,
+    /// The EC or the device database says the command is not supported
+    NotSupported,
+    InvalidArgument(String),
+    /// There is no supported laptop attached to the daemon
+    NoDevice,
+    /// The EC accepted a setting but reads back something else
+    Mismatch(String),
+    /// The daemon could not decode the request, it may be older than the client
+    UnknownCommand,
+    /// The access policy of the daemon doesn't allow this client to run the command
+    PermissionDenied(String),
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeviceError::Transport(e) => write!(f, "communication with the device failed: {}", e),
+            DeviceError::ResponseMismatch => write!(f, "the device sent an unexpected response"),
+            DeviceError::Busy => write!(f, "the device stayed busy after retrying"),
+            DeviceError::Failure => write!(f, "the device reported a failure"),
+            DeviceError::Timeout => write!(f, "the device timed out"),
+            DeviceError::NotSupported => write!(f, "the command is not supported by this device"),
+            DeviceError::InvalidArgument(e) => write!(f, "invalid argument: {}", e),
+            DeviceError::NoDevice => write!(f, "no supported device is attached"),
+            DeviceError::Mismatch(e) => write!(f, "the device did not apply the setting: {}", e),
+            DeviceError::UnknownCommand => write!(f, "the daemon does not know this command, it is probably older than the client"),
+            DeviceError::PermissionDenied(e) => write!(f, "permission denied: {}", e),
+        }
+    }
 }
 
 #[allow(dead_code)]
 pub fn bind() -> Option<UnixStream> {
-    if let Ok(socket) = UnixStream::connect(SOCKET_PATH) {
+    if let Ok(socket) = UnixStream::connect(socket_path()) {
         return Some(socket);
     } else {
         return None;
@@ -48,19 +225,62 @@ pub fn bind() -> Option<UnixStream> {
 #[allow(dead_code)]
 /// We use this from the app, but it should replace bind
 pub fn try_bind() -> std::io::Result<UnixStream> {
-    UnixStream::connect(SOCKET_PATH)
+    UnixStream::connect(socket_path())
+}
+
+/// Removes the socket left by a daemon that didn't get to clean up. Only a
+/// socket of ours that nobody answers on is removed, false when the path is
+/// in use or not ours.
+fn remove_stale_socket(path: &str) -> bool {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return true,
+    };
+    if !metadata.file_type().is_socket() || metadata.uid() != current_uid() {
+        eprintln!("{} exists and is not a socket of ours, not touching it", path);
+        return false;
+    }
+    if UnixStream::connect(path).is_ok() {
+        eprintln!("UNIX Socket {} is in use. Is another daemon running?", path);
+        return false;
+    }
+    eprintln!("Removing the stale socket {}", path);
+    return std::fs::remove_file(path).is_ok();
 }
 
 #[allow(dead_code)]
 pub fn create() -> Option<UnixListener> {
-    if let Ok(_) = std::fs::metadata(SOCKET_PATH) {
-        eprintln!("UNIX Socket already exists. Is another daemon running?");
+    return create_at(&socket_path());
+}
+
+#[allow(dead_code)]
+pub fn create_json() -> Option<UnixListener> {
+    return create_at(&json_socket_path());
+}
+
+fn create_at(path: &str) -> Option<UnixListener> {
+    // The /tmp fallback needs its private directory
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        if !dir.exists() {
+            use std::os::unix::fs::DirBuilderExt;
+            if let Err(e) = std::fs::DirBuilder::new().mode(0o700).create(dir) {
+                eprintln!("Could not create {}: {}", dir.display(), e);
+                return None;
+            }
+        }
+        let uid = current_uid();
+        if dir == std::path::Path::new(&private_dir(uid)) && !is_private_dir(dir, uid) {
+            eprintln!("{} is not a directory private to uid {}, refusing to use it", dir.display(), uid);
+            return None;
+        }
+    }
+    if !remove_stale_socket(path) {
         return None;
     }
-    if let Ok(listener) = UnixListener::bind(SOCKET_PATH) {
-        let mut perms = std::fs::metadata(SOCKET_PATH).unwrap().permissions();
-        perms.set_readonly(false);
-        if std::fs::set_permissions(SOCKET_PATH, perms).is_err() {
+    if let Ok(listener) = UnixListener::bind(path) {
+        // Only the user for now, the daemon opens it up to its access policy
+        use std::os::unix::fs::PermissionsExt;
+        if std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).is_err() {
             eprintln!("Could not set socket permissions");
             return None;
         }
@@ -69,19 +289,52 @@ pub fn create() -> Option<UnixListener> {
     return None;
 }
 
+/// Writes one frame: the payload length as a little endian u32, then the
+/// payload (a bincode encoded `DaemonCommand` or `DaemonResponse`)
+#[allow(dead_code)]
+pub fn write_frame(stream: &mut impl Write, payload: &[u8]) -> std::io::Result<()> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("frame of {} bytes is over the {} bytes limit", payload.len(), MAX_FRAME_SIZE),
+        ));
+    }
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    return stream.write_all(&frame);
+}
+
+/// Reads one frame written by `write_frame`, however the bytes are split.
+/// A length over `MAX_FRAME_SIZE` is refused before reading the payload.
+#[allow(dead_code)]
+pub fn read_frame(stream: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header)?;
+    let len = u32::from_le_bytes(header) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is over the {} bytes limit", len, MAX_FRAME_SIZE),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    return Ok(payload);
+}
+
 #[allow(dead_code)]
 pub fn send_to_daemon(command: DaemonCommand, mut sock: UnixStream) -> Option<DaemonResponse> {
     if let Ok(encoded) = bincode::serialize(&command) {
-        if sock.write_all(&encoded).is_ok() {
-            let mut buf = [0u8; 4096];
-            return match sock.read(&mut buf) {
-                Ok(readed) if readed > 0 => read_from_socked_resp(&buf[0..readed]),
-                Ok(_) => {
+        if write_frame(&mut sock, &encoded).is_ok() {
+            return match read_frame(&mut sock) {
+                Ok(payload) => read_from_socked_resp(&payload),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
                     eprintln!("No response from daemon");
                     None
                 }
-                Err(_) => {
-                    eprintln!("Read failed!");
+                Err(e) => {
+                    eprintln!("Read failed: {}", e);
                     None
                 }
             };
@@ -92,6 +345,100 @@ pub fn send_to_daemon(command: DaemonCommand, mut sock: UnixStream) -> Option<Da
     return None;
 }
 
+/// A connection kept open for several commands
+#[allow(dead_code)]
+pub struct Connection {
+    stream: UnixStream,
+    next_id: u32,
+}
+
+#[allow(dead_code)]
+impl Connection {
+    pub fn open() -> std::io::Result<Connection> {
+        return Ok(Connection { stream: try_bind()?, next_id: 0 });
+    }
+
+    /// Sends a command and waits for its answer
+    pub fn request(&mut self, command: DaemonCommand) -> std::io::Result<DaemonResponse> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let request = DaemonCommand::Request { id, command: Box::new(command) };
+        let encoded = bincode::serialize(&request)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        write_frame(&mut self.stream, &encoded)?;
+        loop {
+            match bincode::deserialize::<DaemonResponse>(&read_frame(&mut self.stream)?) {
+                Ok(DaemonResponse::Reply { id: reply_id, response }) if reply_id == id => return Ok(*response),
+                // Left over from a request we gave up on
+                Ok(DaemonResponse::Reply { .. }) => continue,
+                Ok(response) => return Ok(response),
+                Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            }
+        }
+    }
+}
+
+/// Opens a connection that receives the daemon events, read them with `read_event`
+#[allow(dead_code)]
+pub fn subscribe() -> std::io::Result<UnixStream> {
+    let mut sock = try_bind()?;
+    let encoded = bincode::serialize(&DaemonCommand::Subscribe)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    write_frame(&mut sock, &encoded)?;
+    match bincode::deserialize::<DaemonResponse>(&read_frame(&mut sock)?) {
+        Ok(DaemonResponse::Subscribed) => Ok(sock),
+        Ok(response) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("unexpected answer {:?}", response))),
+        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+    }
+}
+
+/// Waits for the next event on a `subscribe` connection
+#[allow(dead_code)]
+pub fn read_event(sock: &mut UnixStream) -> std::io::Result<DaemonEvent> {
+    loop {
+        match bincode::deserialize::<DaemonResponse>(&read_frame(sock)?) {
+            Ok(DaemonResponse::Event { event }) => return Ok(event),
+            // Events of a newer daemon
+            Ok(_) | Err(_) => continue,
+        }
+    }
+}
+
+/// The answer of the daemon to `Hello`
+#[allow(dead_code)]
+pub fn hello_response() -> DaemonResponse {
+    return DaemonResponse::Hello {
+        daemon_version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol: PROTOCOL_VERSION,
+        commands: COMMANDS.iter().map(|c| c.to_string()).collect(),
+    };
+}
+
+/// Checks that the daemon speaks our protocol, returns the commands it knows.
+/// The error is a message for the user.
+#[allow(dead_code)]
+pub fn handshake(client: &str) -> Result<Vec<String>, String> {
+    let socket = try_bind().map_err(|e| format!("Can't connect to the daemon: {}", e))?;
+    let hello = DaemonCommand::Hello { client: client.to_string(), protocol: PROTOCOL_VERSION };
+    match send_to_daemon(hello, socket) {
+        Some(DaemonResponse::Hello { protocol, commands, .. }) if protocol == PROTOCOL_VERSION => Ok(commands),
+        Some(DaemonResponse::Hello { daemon_version, protocol, .. }) => Err(format!(
+            "The daemon (version {}) speaks protocol {}, {} speaks {}. Install matching versions",
+            daemon_version, protocol, client, PROTOCOL_VERSION
+        )),
+        // Daemons from before the handshake know no `Hello`
+        Some(DaemonResponse::Error { error: DeviceError::UnknownCommand }) => Err(format!(
+            "The daemon is too old for {}, it speaks a protocol older than version 1. Please upgrade razercontrol and restart the service",
+            client
+        )),
+        Some(DaemonResponse::Error { error }) => Err(format!("The daemon refused the handshake: {}", error)),
+        _ => Err(format!(
+            "The daemon did not answer the handshake, it is probably older than {}. Install matching versions",
+            client
+        )),
+    }
+}
+
 /// Deserializes incomming bytes in order to return
 /// a `DaemonResponse`. None is returned if deserializing failed
 fn read_from_socked_resp(bytes: &[u8]) -> Option<DaemonResponse> {
@@ -122,3 +469,59 @@ pub fn read_from_socket_req(bytes: &[u8]) -> Option<DaemonCommand> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One of each command. The match has no wildcard so that a new variant
+    /// doesn't build until it is listed here, and in `COMMANDS`.
+    fn every_command() -> Vec<DaemonCommand> {
+        let commands = vec![
+            DaemonCommand::SetFanSpeed { ac: 1, rpm: 4000 },
+            DaemonCommand::GetFanSpeed { ac: 1 },
+            DaemonCommand::SetPowerMode { ac: 1, pwr: 4, cpu: 3, gpu: 2 },
+            DaemonCommand::GetPwrLevel { ac: 1 },
+            DaemonCommand::GetCPUBoost { ac: 1 },
+            DaemonCommand::GetGPUBoost { ac: 1 },
+            DaemonCommand::SetIdle { ac: 1, val: 5 },
+            DaemonCommand::SetBatteryHealthOptimizer { is_on: true, threshold: 80 },
+            DaemonCommand::GetBatteryHealthOptimizer(),
+            DaemonCommand::GetDeviceName,
+            DaemonCommand::SetZoneFanSpeed { ac: 1, zone: 1, rpm: 4000 },
+            DaemonCommand::GetZoneFanSpeed { ac: 1, zone: 1 },
+            DaemonCommand::GetFeatures,
+            DaemonCommand::GetLimits,
+            DaemonCommand::GetDeviceInfo,
+            DaemonCommand::Hello { client: "test".to_string(), protocol: PROTOCOL_VERSION },
+            DaemonCommand::Subscribe,
+            DaemonCommand::Request { id: 1, command: Box::new(DaemonCommand::GetDeviceName) },
+        ];
+        for command in &commands {
+            match command {
+                DaemonCommand::SetFanSpeed { .. } | DaemonCommand::GetFanSpeed { .. }
+                | DaemonCommand::SetPowerMode { .. } | DaemonCommand::GetPwrLevel { .. }
+                | DaemonCommand::GetCPUBoost { .. } | DaemonCommand::GetGPUBoost { .. }
+                | DaemonCommand::SetIdle { .. } | DaemonCommand::SetBatteryHealthOptimizer { .. }
+                | DaemonCommand::GetBatteryHealthOptimizer() | DaemonCommand::GetDeviceName
+                | DaemonCommand::SetZoneFanSpeed { .. } | DaemonCommand::GetZoneFanSpeed { .. }
+                | DaemonCommand::GetFeatures | DaemonCommand::GetLimits | DaemonCommand::GetDeviceInfo
+                | DaemonCommand::Hello { .. } | DaemonCommand::Subscribe | DaemonCommand::Request { .. } => {},
+            }
+        }
+        return commands;
+    }
+
+    #[test]
+    fn commands_follow_the_variant_order() {
+        let commands = every_command();
+        assert_eq!(commands.len(), COMMANDS.len());
+        for command in commands {
+            let encoded = bincode::serialize(&command).unwrap();
+            let index = u32::from_le_bytes([encoded[0], encoded[1], encoded[2], encoded[3]]) as usize;
+            let debug = format!("{:?}", command);
+            let name = debug.split([' ', '(']).next().unwrap_or_default();
+            assert_eq!(name, COMMANDS[index]);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use crate::comms::DaemonCommand;
+use crate::config::AccessPolicy;
+
+/// The process on the other end of a connection, from SO_PEERCRED
+pub struct Peer {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Peer {
+    pub fn of(stream: &UnixStream) -> io::Result<Peer> {
+        let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(Peer { pid: cred.pid, uid: cred.uid, gid: cred.gid });
+    }
+
+    /// Groups of the peer user in the group database. Looked up by uid: by
+    /// the time a request comes in the pid may belong to another process.
+    fn groups(&self) -> Vec<u32> {
+        let mut groups = vec![self.gid];
+        let name = match user_name(self.uid) {
+            Some(name) => name,
+            None => return groups,
+        };
+        let mut count: libc::c_int = 32;
+        loop {
+            let mut list: Vec<libc::gid_t> = vec![0; count as usize];
+            let size = list.len();
+            let ret = unsafe { libc::getgrouplist(name.as_ptr(), self.gid, list.as_mut_ptr(), &mut count) };
+            if ret >= 0 {
+                list.truncate(count as usize);
+                groups.extend(list);
+                return groups;
+            }
+            // `count` now is the number of groups, try again with that much room
+            if count as usize <= size {
+                return groups;
+            }
+        }
+    }
+
+    fn in_group(&self, name: &str) -> bool {
+        match group_id(name) {
+            Some(gid) => self.groups().contains(&gid),
+            None => false,
+        }
+    }
+}
+
+/// Whether `peer` may run `command`. The user the daemon runs as and root may
+/// do everything, members of `policy.group` too. Everybody else may only use
+/// the read-only commands, and only when `policy.others_can_read` is set.
+/// `Hello` is always answered, so clients can tell a refusal from a mismatch.
+pub fn check(policy: &AccessPolicy, peer: &Peer, command: &DaemonCommand) -> Result<(), String> {
+    let owner = unsafe { libc::getuid() };
+    if peer.uid == owner || peer.uid == 0 || matches!(command, DaemonCommand::Hello { .. }) {
+        return Ok(());
+    }
+    if let Some(group) = &policy.group {
+        if peer.in_group(group) {
+            return Ok(());
+        }
+    }
+    if command.is_read_only() && policy.others_can_read {
+        return Ok(());
+    }
+
+    let what = if command.is_read_only() { "read" } else { "change" };
+    return Err(format!("uid {} may not {} the settings of this daemon", peer.uid, what));
+}
+
+fn user_name(uid: u32) -> Option<CString> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf: Vec<libc::c_char> = vec![0; 4096];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let ret = unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if ret != 0 || result.is_null() {
+        return None;
+    }
+    return Some(unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned());
+}
+
+fn group_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf: Vec<libc::c_char> = vec![0; 4096];
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let ret = unsafe { libc::getgrnam_r(name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if ret != 0 || result.is_null() {
+        return None;
+    }
+    return Some(group.gr_gid);
+}
+
+/// Opens the socket at `path` (created 0600) to the ones `policy` lets in:
+/// 0660 and owned by `policy.group`, read and write for everybody with
+/// `policy.others_can_read`. They still need to reach its directory.
+pub fn share_socket(policy: &AccessPolicy, path: &str) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut mode = 0o600;
+    if let Some(group) = &policy.group {
+        let gid = group_id(group)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no group {}", group)))?;
+        let c_path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // uid -1 keeps the owner
+        if unsafe { libc::chown(c_path.as_ptr(), u32::MAX, gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        mode |= 0o060;
+    }
+    if policy.others_can_read {
+        mode |= 0o006;
+    }
+    return std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode));
+}
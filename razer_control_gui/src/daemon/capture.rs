@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::command::{self, EcCommand};
+use crate::packet::{self, RazerPacket};
+
+/// When set, every EC exchange is appended to this file (one JSON per line)
+pub const CAPTURE_FILE_ENV: &str = "RAZER_LAPTOP_CONTROL_CAPTURE";
+
+/// Human readable view of a packet, `raw` keeps the exact bytes for replay
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DecodedPacket {
+    pub status: u8,
+    pub status_name: String,
+    pub command_class: u8,
+    pub command_id: u8,
+    pub data_size: u8,
+    pub args: Vec<u8>,
+    pub crc: u8,
+    pub raw: String,
+}
+
+/// One attempt of `RazerLaptop::send_report`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CaptureRecord {
+    pub timestamp_ms: u64,
+    /// 0 for the first try, then one more per retry
+    pub attempt: u8,
+    pub request: DecodedPacket,
+    pub response: Option<DecodedPacket>,
+    pub error: Option<String>,
+}
+
+pub struct Capture {
+    file: File,
+}
+
+#[allow(dead_code)]
+impl Capture {
+    /// Opens the capture file named by `CAPTURE_FILE_ENV`, if any
+    pub fn from_env() -> Option<Capture> {
+        let path = std::env::var(CAPTURE_FILE_ENV).ok()?;
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => {
+                println!("Capturing EC traffic to {}", path);
+                Some(Capture { file })
+            },
+            Err(e) => {
+                eprintln!("Error opening capture file {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Appends an exchange, with the serial number blanked out
+    pub fn record(&mut self, attempt: u8, request: &[u8], response: Option<&[u8]>, error: Option<String>) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let record = CaptureRecord {
+            timestamp_ms,
+            attempt,
+            request: decode(request),
+            response: response.map(|response| decode(&redact(request, response))),
+            error,
+        };
+
+        let result = serde_json::to_string(&record)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.file, "{}", line));
+        if let Err(e) = result {
+            eprintln!("Error writing capture: {}", e);
+        }
+    }
+
+    pub fn read(path: &str) -> io::Result<Vec<CaptureRecord>> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut records = vec![];
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push(serde_json::from_str(&line)?);
+        }
+
+        Ok(records)
+    }
+}
+
+fn decode(bytes: &[u8]) -> DecodedPacket {
+    let raw: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    match bincode::deserialize::<RazerPacket>(bytes) {
+        Ok(packet) => {
+            let size = (packet.data_size as usize).min(packet.args.len());
+            DecodedPacket {
+                status: packet.status,
+                status_name: status_name(packet.status).into(),
+                command_class: packet.command_class,
+                command_id: packet.command_id,
+                data_size: packet.data_size,
+                args: packet.args[..size].to_vec(),
+                crc: packet.crc,
+                raw,
+            }
+        },
+        Err(_) => DecodedPacket {
+            status: 0,
+            status_name: "undecodable".into(),
+            command_class: 0,
+            command_id: 0,
+            data_size: 0,
+            args: vec![],
+            crc: 0,
+            raw,
+        }
+    }
+}
+
+/// Zeroes the serial number in the answer to `GetSerial`. Captures get
+/// attached to bug reports, the serial identifies the laptop.
+fn redact(request: &[u8], response: &[u8]) -> Vec<u8> {
+    let is_serial = bincode::deserialize::<RazerPacket>(request).ok()
+        .and_then(|request| EcCommand::decode(&request)) == Some(EcCommand::GetSerial);
+    match bincode::deserialize::<RazerPacket>(response) {
+        Ok(mut packet) if is_serial => {
+            let size = command::SERIAL_SIZE.min(packet.args.len());
+            packet.args[..size].iter_mut().for_each(|b| *b = 0);
+            packet.calc_crc()
+        },
+        _ => response.to_vec(),
+    }
+}
+
+#[allow(dead_code)]
+/// Turns the hex string of `DecodedPacket::raw` back into a report
+pub fn raw_bytes(packet: &DecodedPacket) -> Option<Vec<u8>> {
+    if packet.raw.len() != packet::REPORT_SIZE * 2 {
+        return None;
+    }
+    (0..packet.raw.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&packet.raw[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn status_name(status: u8) -> &'static str {
+    match status {
+        RazerPacket::RAZER_CMD_NEW => "new",
+        RazerPacket::RAZER_CMD_BUSY => "busy",
+        RazerPacket::RAZER_CMD_SUCCESSFUL => "successful",
+        RazerPacket::RAZER_CMD_FAILURE => "failure",
+        RazerPacket::RAZER_CMD_TIMEOUT => "timeout",
+        RazerPacket::RAZER_CMD_NOT_SUPPORTED => "not supported",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,298 @@
+use crate::packet::RazerPacket;
+
+/// Length of the serial number answered to `GetSerial`
+pub const SERIAL_SIZE: usize = 22;
+
+/// Which processor a boost command talks about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoostTarget {
+    Cpu = 0x01,
+    Gpu = 0x02,
+}
+
+impl BoostTarget {
+    fn from_byte(b: u8) -> Option<BoostTarget> {
+        match b {
+            0x01 => Some(BoostTarget::Cpu),
+            0x02 => Some(BoostTarget::Gpu),
+            _ => None,
+        }
+    }
+}
+
+/// Commands we know how to send to the EC.
+///
+/// Zones are the EC fan/power zones, 0x01 and 0x02. Fan speeds are in RPM, the
+/// EC works in hundreds of RPM so they get truncated on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcCommand {
+    GetPowerMode { zone: u8 },
+    SetPowerMode { zone: u8, mode: u8, manual_fan: bool },
+    GetFanRpm { zone: u8 },
+    SetFanRpm { zone: u8, rpm: u16 },
+    GetBoost { target: BoostTarget },
+    SetBoost { target: BoostTarget, level: u8 },
+    GetBho,
+    SetBho { is_on: bool, threshold: u8 },
+    /// Standard Razer device queries (class 0x00)
+    GetFirmwareVersion,
+    GetSerial,
+}
+
+/// What the EC answered to an `EcCommand`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcResponse {
+    PowerMode { zone: u8, mode: u8, manual_fan: bool },
+    FanRpm { zone: u8, rpm: u16 },
+    Boost { target: BoostTarget, level: u8 },
+    Bho { is_on: bool, threshold: u8 },
+    FirmwareVersion { major: u8, minor: u8 },
+    /// ASCII, padded with zeros
+    Serial { serial: [u8; SERIAL_SIZE] },
+    /// Set commands only acknowledge
+    Done,
+}
+
+impl EcCommand {
+    /// Command class, command id and data size of every command.
+    ///
+    /// Adding a command means adding a line here and its argument layout to
+    /// `encode`/`decode`.
+    fn header(&self) -> (u8, u8, u8) {
+        match self {
+            EcCommand::GetPowerMode { .. } => (0x0d, 0x82, 0x04),
+            EcCommand::SetPowerMode { .. } => (0x0d, 0x02, 0x04),
+            EcCommand::GetFanRpm { .. }    => (0x0d, 0x81, 0x03),
+            EcCommand::SetFanRpm { .. }    => (0x0d, 0x01, 0x03),
+            EcCommand::GetBoost { .. }     => (0x0d, 0x87, 0x03),
+            EcCommand::SetBoost { .. }     => (0x0d, 0x07, 0x03),
+            EcCommand::GetBho              => (0x07, 0x92, 0x01),
+            EcCommand::SetBho { .. }       => (0x07, 0x12, 0x01),
+            EcCommand::GetFirmwareVersion  => (0x00, 0x81, 0x02),
+            EcCommand::GetSerial           => (0x00, 0x82, SERIAL_SIZE as u8),
+        }
+    }
+
+    /// Whether the command only reads from the EC
+    pub fn is_query(&self) -> bool {
+        match self {
+            EcCommand::GetPowerMode { .. } | EcCommand::GetFanRpm { .. } | EcCommand::GetBoost { .. } | EcCommand::GetBho => true,
+            EcCommand::GetFirmwareVersion | EcCommand::GetSerial => true,
+            _ => false,
+        }
+    }
+
+    /// Builds the request packet, the CRC is left to `send_report`. Fails
+    /// for arguments that don't fit the EC encoding.
+    pub fn encode(&self) -> Result<RazerPacket, String> {
+        let (class, id, size) = self.header();
+        let mut packet = RazerPacket::new(class, id, size);
+        match *self {
+            EcCommand::GetPowerMode { zone } | EcCommand::GetFanRpm { zone } => {
+                packet.args[1] = zone;
+            },
+            EcCommand::SetPowerMode { zone, mode, manual_fan } => {
+                packet.args[1] = zone;
+                packet.args[2] = mode;
+                packet.args[3] = manual_fan as u8;
+            },
+            EcCommand::SetFanRpm { zone, rpm } => {
+                packet.args[1] = zone;
+                packet.args[2] = rpm_to_byte(rpm);
+            },
+            EcCommand::GetBoost { target } => {
+                packet.args[1] = target as u8;
+            },
+            EcCommand::SetBoost { target, level } => {
+                packet.args[1] = target as u8;
+                packet.args[2] = level;
+            },
+            EcCommand::GetBho | EcCommand::GetFirmwareVersion | EcCommand::GetSerial => {},
+            EcCommand::SetBho { is_on, threshold } => {
+                // The top bit is the on/off flag
+                if threshold > 0b0111_1111 {
+                    return Err(format!("BHO threshold {} does not fit in 7 bits", threshold));
+                }
+                packet.args[0] = bho_to_byte(is_on, threshold);
+            },
+        }
+
+        return Ok(packet);
+    }
+
+    /// Recognizes a request packet, used by whoever plays the EC side
+    pub fn decode(packet: &RazerPacket) -> Option<EcCommand> {
+        let args = &packet.args;
+        let command = match (packet.command_class, packet.command_id) {
+            (0x0d, 0x82) => EcCommand::GetPowerMode { zone: args[1] },
+            (0x0d, 0x02) => EcCommand::SetPowerMode { zone: args[1], mode: args[2], manual_fan: args[3] != 0 },
+            (0x0d, 0x81) => EcCommand::GetFanRpm { zone: args[1] },
+            (0x0d, 0x01) => EcCommand::SetFanRpm { zone: args[1], rpm: args[2] as u16 * 100 },
+            (0x0d, 0x87) => EcCommand::GetBoost { target: BoostTarget::from_byte(args[1])? },
+            (0x0d, 0x07) => EcCommand::SetBoost { target: BoostTarget::from_byte(args[1])?, level: args[2] },
+            (0x07, 0x92) => EcCommand::GetBho,
+            (0x00, 0x81) => EcCommand::GetFirmwareVersion,
+            (0x00, 0x82) => EcCommand::GetSerial,
+            (0x07, 0x12) => {
+                let (is_on, threshold) = byte_to_bho(args[0]);
+                EcCommand::SetBho { is_on, threshold }
+            },
+            _ => return None,
+        };
+
+        return Some(command);
+    }
+
+    /// Reads the answer to this command out of the response packet
+    pub fn decode_response(&self, packet: &RazerPacket) -> EcResponse {
+        let args = &packet.args;
+        match *self {
+            EcCommand::GetPowerMode { zone } => EcResponse::PowerMode { zone, mode: args[2], manual_fan: args[3] != 0 },
+            EcCommand::GetFanRpm { zone } => EcResponse::FanRpm { zone, rpm: args[2] as u16 * 100 },
+            EcCommand::GetBoost { target } => EcResponse::Boost { target, level: args[2] },
+            EcCommand::GetBho => {
+                let (is_on, threshold) = byte_to_bho(args[0]);
+                EcResponse::Bho { is_on, threshold }
+            },
+            EcCommand::GetFirmwareVersion => EcResponse::FirmwareVersion { major: args[0], minor: args[1] },
+            EcCommand::GetSerial => {
+                let mut serial = [0u8; SERIAL_SIZE];
+                serial.copy_from_slice(&args[..SERIAL_SIZE]);
+                EcResponse::Serial { serial }
+            },
+            _ => EcResponse::Done,
+        }
+    }
+
+    /// Writes `response` into the answer packet, the inverse of
+    /// `decode_response`. Status and CRC are left to the caller.
+    #[allow(dead_code)] // the daemon only plays the EC in its tests
+    pub fn encode_response(&self, response: &EcResponse, packet: &mut RazerPacket) {
+        let args = &mut packet.args;
+        match *response {
+            EcResponse::PowerMode { mode, manual_fan, .. } => {
+                args[2] = mode;
+                args[3] = manual_fan as u8;
+            },
+            EcResponse::FanRpm { rpm, .. } => args[2] = rpm_to_byte(rpm),
+            EcResponse::Boost { level, .. } => args[2] = level,
+            EcResponse::Bho { is_on, threshold } => args[0] = bho_to_byte(is_on, threshold),
+            EcResponse::FirmwareVersion { major, minor } => {
+                args[0] = major;
+                args[1] = minor;
+            },
+            EcResponse::Serial { serial } => args[..SERIAL_SIZE].copy_from_slice(&serial),
+            EcResponse::Done => {},
+        }
+    }
+}
+
+fn rpm_to_byte(rpm: u16) -> u8 {
+    return (rpm / 100).min(u8::MAX as u16) as u8;
+}
+
+// top bit flags whether battery health optimization is on or off
+// bottom bits are the actual threshold that it is set to
+fn byte_to_bho(u: u8) -> (bool, u8) {
+    return (u & (1 << 7) != 0, (u & 0b0111_1111));
+}
+
+fn bho_to_byte(is_on: bool, threshold: u8) -> u8 {
+    if is_on {
+        return threshold | 0b1000_0000;
+    }
+    return threshold;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serial() -> [u8; SERIAL_SIZE] {
+        let mut serial = [0u8; SERIAL_SIZE];
+        serial[..13].copy_from_slice(b"SIM02B6000000");
+        return serial;
+    }
+
+    /// Every command with an answer it could get
+    fn exchanges() -> Vec<(EcCommand, EcResponse)> {
+        return vec![
+            (EcCommand::GetPowerMode { zone: 0x01 }, EcResponse::PowerMode { zone: 0x01, mode: 4, manual_fan: true }),
+            (EcCommand::GetPowerMode { zone: 0x02 }, EcResponse::PowerMode { zone: 0x02, mode: 1, manual_fan: false }),
+            (EcCommand::SetPowerMode { zone: 0x01, mode: 4, manual_fan: true }, EcResponse::Done),
+            (EcCommand::SetPowerMode { zone: 0x02, mode: 0, manual_fan: false }, EcResponse::Done),
+            (EcCommand::GetFanRpm { zone: 0x01 }, EcResponse::FanRpm { zone: 0x01, rpm: 3500 }),
+            (EcCommand::GetFanRpm { zone: 0x02 }, EcResponse::FanRpm { zone: 0x02, rpm: 0 }),
+            (EcCommand::SetFanRpm { zone: 0x01, rpm: 4300 }, EcResponse::Done),
+            (EcCommand::GetBoost { target: BoostTarget::Cpu }, EcResponse::Boost { target: BoostTarget::Cpu, level: 3 }),
+            (EcCommand::GetBoost { target: BoostTarget::Gpu }, EcResponse::Boost { target: BoostTarget::Gpu, level: 2 }),
+            (EcCommand::SetBoost { target: BoostTarget::Cpu, level: 3 }, EcResponse::Done),
+            (EcCommand::SetBoost { target: BoostTarget::Gpu, level: 0 }, EcResponse::Done),
+            (EcCommand::GetBho, EcResponse::Bho { is_on: true, threshold: 80 }),
+            (EcCommand::GetBho, EcResponse::Bho { is_on: false, threshold: 50 }),
+            (EcCommand::SetBho { is_on: true, threshold: 80 }, EcResponse::Done),
+            (EcCommand::SetBho { is_on: false, threshold: 65 }, EcResponse::Done),
+            (EcCommand::GetFirmwareVersion, EcResponse::FirmwareVersion { major: 1, minor: 2 }),
+            (EcCommand::GetSerial, EcResponse::Serial { serial: serial() }),
+        ];
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        for (command, _) in exchanges() {
+            let packet = command.encode().unwrap();
+            assert_eq!((packet.command_class, packet.command_id, packet.data_size), command.header());
+            assert_eq!(EcCommand::decode(&packet), Some(command));
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        for (command, response) in exchanges() {
+            let mut packet = command.encode().unwrap();
+            command.encode_response(&response, &mut packet);
+            assert_eq!(command.decode_response(&packet), response, "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn unknown_commands_are_not_decoded() {
+        assert_eq!(EcCommand::decode(&RazerPacket::new(0x0f, 0x02, 0x04)), None);
+        let mut boost = EcCommand::GetBoost { target: BoostTarget::Cpu }.encode().unwrap();
+        boost.args[1] = 0x03;
+        assert_eq!(EcCommand::decode(&boost), None);
+    }
+
+    #[test]
+    fn rpm_is_truncated_to_hundreds() {
+        let packet = EcCommand::SetFanRpm { zone: 0x01, rpm: 3550 }.encode().unwrap();
+        assert_eq!(packet.args[2], 35);
+        assert_eq!(EcCommand::decode(&packet), Some(EcCommand::SetFanRpm { zone: 0x01, rpm: 3500 }));
+
+        let command = EcCommand::GetFanRpm { zone: 0x02 };
+        let mut packet = command.encode().unwrap();
+        command.encode_response(&EcResponse::FanRpm { zone: 0x02, rpm: 4399 }, &mut packet);
+        assert_eq!(command.decode_response(&packet), EcResponse::FanRpm { zone: 0x02, rpm: 4300 });
+    }
+
+    #[test]
+    fn rpm_saturates_at_one_byte() {
+        let packet = EcCommand::SetFanRpm { zone: 0x01, rpm: 30000 }.encode().unwrap();
+        assert_eq!(packet.args[2], u8::MAX);
+    }
+
+    #[test]
+    fn bho_flag_is_the_top_bit() {
+        assert_eq!(EcCommand::SetBho { is_on: true, threshold: 80 }.encode().unwrap().args[0], 0b1101_0000);
+        assert_eq!(EcCommand::SetBho { is_on: false, threshold: 80 }.encode().unwrap().args[0], 0b0101_0000);
+        assert_eq!(byte_to_bho(0b1011_0010), (true, 50));
+        assert_eq!(byte_to_bho(0b0011_0010), (false, 50));
+    }
+
+    #[test]
+    fn bho_threshold_must_not_reach_the_top_bit() {
+        // Thresholds are percentages, a value of 128 or more would turn into the flag
+        assert!(EcCommand::SetBho { is_on: false, threshold: 0b1000_0001 }.encode().is_err());
+        assert!(EcCommand::SetBho { is_on: true, threshold: 0b0111_1111 }.encode().is_ok());
+    }
+}
@@ -10,6 +10,9 @@ pub struct PowerConfig {
     pub cpu_boost: u8,
     pub gpu_boost: u8,
     pub fan_rpm: i32,
+    /// Per fan zone speeds, `None` when every zone follows `fan_rpm`
+    #[serde(default)]
+    pub zone_fan_rpm: Option<[i32; 2]>,
     pub screensaver: bool, // turno of keyboard light if screen is blank
     pub idle: u32,
 }
@@ -21,21 +24,43 @@ impl PowerConfig {
             cpu_boost: 1,
             gpu_boost: 0,
             fan_rpm: 0,
+            zone_fan_rpm: None,
             screensaver: false,
             idle: 0,
         }
     }
+
+    pub fn fan_rpm_for(&self, zone: usize) -> i32 {
+        match self.zone_fan_rpm {
+            Some(rpm) => rpm[zone],
+            None => self.fan_rpm,
+        }
+    }
+}
+
+/// Who may use the daemon socket besides the user the daemon runs as (and root)
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct AccessPolicy {
+    /// Members of this group may change settings too
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Whether everybody else may use the read-only (Get*) commands
+    #[serde(default)]
+    pub others_can_read: bool,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Configuration {
     pub power: [PowerConfig; 2],
+    #[serde(default)]
+    pub access: AccessPolicy,
 }
 
 impl Configuration {
     pub fn new() -> Configuration {
         return Configuration {
             power: [PowerConfig::new(), PowerConfig::new()],
+            access: AccessPolicy::default(),
         };
     }
 
@@ -1,6 +1,8 @@
-use std::io::{Read, Write};
-use std::os::unix::net::UnixStream;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::thread::{self, JoinHandle};
 use std::time;
 
@@ -13,13 +15,32 @@ use dbus::{Message, arg};
 
 #[path = "../comms.rs"]
 mod comms;
+mod access;
 mod config;
 mod device;
+mod events;
 mod battery;
+mod capture;
+mod command;
 mod dbus_mutter_displayconfig;
 mod dbus_mutter_idlemonitor;
 mod screensaver;
 mod login1;
+mod idle;
+mod onboard;
+mod packet;
+mod replay;
+mod transport;
+mod worker;
+
+/// How long a client may keep the connection open without sending anything
+const CLIENT_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+/// Clients served at once over both sockets, each one takes a thread
+const MAX_CLIENTS: usize = 32;
+/// How often a subscriber without events is checked for a hangup
+const HANGUP_CHECK: time::Duration = time::Duration::from_secs(1);
+
+static CLIENTS: AtomicUsize = AtomicUsize::new(0);
 
 lazy_static! {
     // static ref CONFIG: Mutex<config::Configuration> = {
@@ -28,12 +49,12 @@ lazy_static! {
             // Err(_) => Mutex::new(config::Configuration::new()),
         // }
     // };
-    static ref DEV_MANAGER: Mutex<device::DeviceManager> = {
-        match device::DeviceManager::read_laptops_file() {
-            Ok(c) => Mutex::new(c),
-            Err(_) => Mutex::new(device::DeviceManager::new()),
-        }
-    };
+    static ref IDLE: Mutex<idle::IdleWatch> = Mutex::new(idle::IdleWatch::new());
+    static ref EVENTS: events::EventBus = events::EventBus::new();
+    // Read once, changes need a restart
+    static ref ACCESS: config::AccessPolicy = config::Configuration::read_from_config()
+        .map(|c| c.access)
+        .unwrap_or_default();
 }
 
 // Main function for daemon
@@ -41,45 +62,75 @@ fn main() {
     setup_panic_hook();
     init_logging();
 
-    if let Ok(mut d) = DEV_MANAGER.lock() {
-        d.discover_devices();
-        if let Some(laptop) = d.get_device() {
-            println!("supported device: {:?}", laptop.get_name());
-        } else {
-            println!("no supported device found");
-            std::process::exit(1);
-        }
-    } else {
-        println!("error loading supported devices");
-        std::process::exit(1);
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(path) = args.iter().position(|arg| arg == "--socket").and_then(|i| args.get(i + 1)) {
+        std::env::set_var(comms::SOCKET_ENV, path);
     }
 
+    if std::env::args().nth(1).as_deref() == Some("--onboard") {
+        let out_dir = std::env::args().nth(2).unwrap_or_else(|| ".".to_string());
+        let found = onboard::run(&out_dir);
+        std::process::exit(if found { 0 } else { 1 });
+    }
+    if std::env::args().nth(1).as_deref() == Some("--replay") {
+        let code = match std::env::args().nth(2).and_then(|path| replay::run(&path)) {
+            Some(0) => 0,
+            Some(_) => 2,
+            None => 1,
+        };
+        std::process::exit(code);
+    }
 
-    if let Ok(mut d) = DEV_MANAGER.lock() {
-        let dbus_system = Connection::new_system()
-            .expect("failed to connect to D-Bus system bus");
-        let proxy_ac = dbus_system.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/line_power_AC0", time::Duration::from_millis(5000));
-        use battery::OrgFreedesktopUPowerDevice;
-        if let Ok(online) = proxy_ac.online() {
-            info!("AC0 online: {:?}", online);
-            d.set_ac_state(online);
-        } else {
-            println!("error getting current power state");
-            std::process::exit(1);
-        }
+    let mut d = match device::DeviceManager::read_laptops_file() {
+        Ok(c) => c,
+        Err(_) => device::DeviceManager::new(),
+    };
+    d.discover_devices();
+    if let Some(laptop) = d.get_device() {
+        println!("supported device: {:?}", laptop.get_name());
+        laptop.probe_features();
+        laptop.read_identity();
+    } else {
+        // Clients get told there is no device until udev reports one
+        println!("no supported device found, waiting for one");
     }
 
-    start_screensaver_monitor_task();
-    start_battery_monitor_task();
+    let dbus_system = Connection::new_system()
+        .expect("failed to connect to D-Bus system bus");
+    let proxy_ac = dbus_system.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/line_power_AC0", time::Duration::from_millis(5000));
+    use battery::OrgFreedesktopUPowerDevice;
+    if let Ok(online) = proxy_ac.online() {
+        info!("AC0 online: {:?}", online);
+        d.set_ac_state(online);
+    } else if d.is_simulated() {
+        // A laptop-less box usually has no AC0, pretend we are plugged in
+        warn!("error getting current power state, assuming AC for the simulator");
+        d.set_ac_state(true);
+    } else {
+        println!("error getting current power state");
+        std::process::exit(1);
+    }
+
+    // From now on the device is only touched from its own thread
+    let worker = worker::DeviceWorker::start(d, &IDLE, &EVENTS);
+
+    start_screensaver_monitor_task(worker.clone());
+    start_battery_monitor_task(worker.clone());
+    start_hotplug_monitor_task(worker.clone());
     let clean_thread = start_shutdown_task();
 
+    match comms::create_json() {
+        Some(listener) => {
+            share_socket(&comms::json_socket_path());
+            let worker = worker.clone();
+            thread::spawn(move || serve(listener, Protocol::Json, worker));
+        },
+        // Scripting is a nicety, the GUI and the CLI still work without it
+        None => warn!("Could not create the JSON socket {}", comms::json_socket_path()),
+    }
     if let Some(listener) = comms::create() {
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => handle_data(stream),
-                Err(_) => {} // Don't care about this
-            }
-        }
+        share_socket(&comms::socket_path());
+        serve(listener, Protocol::Bincode, worker);
     } else {
         eprintln!("Could not create Unix socket!");
         std::process::exit(1);
@@ -87,14 +138,24 @@ fn main() {
     clean_thread.join().unwrap();
 }
 
+/// Applies the access policy to the socket mode, it stays private on failure
+fn share_socket(path: &str) {
+    if let Err(e) = access::share_socket(&ACCESS, path) {
+        warn!("Could not open {} to the access policy: {}", path, e);
+    }
+}
+
+fn remove_sockets() {
+    let _ = std::fs::remove_file(comms::socket_path());
+    let _ = std::fs::remove_file(comms::json_socket_path());
+}
+
 /// Installs a custom panic hook to perform cleanup when the daemon crashes
 fn setup_panic_hook() {
     let default_panic_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |info| {
         error!("Something went wrong! Removing the socket path");
-        if std::fs::metadata(comms::SOCKET_PATH).is_ok() {
-            std::fs::remove_file(comms::SOCKET_PATH).unwrap();
-        }
+        remove_sockets();
         default_panic_hook(info);
     }));
 }
@@ -109,50 +170,49 @@ fn init_logging() {
 }
 
 
-fn start_screensaver_monitor_task() -> JoinHandle<()> {
+fn start_screensaver_monitor_task(worker: worker::DeviceWorker) -> JoinHandle<()> {
     thread::spawn(move || {
         let dbus_session = Connection::new_session()
             .expect("failed to connect to D-Bus session bus");
         let  proxy = dbus_session.with_proxy("org.gnome.Mutter.DisplayConfig", "/org/gnome/Mutter/DisplayConfig", time::Duration::from_millis(5000));
-        let _id = proxy.match_signal(|h: dbus_mutter_displayconfig::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+        let w = worker.clone();
+        let _id = proxy.match_signal(move |h: dbus_mutter_displayconfig::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
             let online: Option<&i32> = arg::prop_cast(&h.changed_properties, "PowerSaveMode");
             if let Some(online) = online {
                 if *online == 3 {
-                    if let Ok(mut d) = DEV_MANAGER.lock() {
-                        d.light_off();
-                    }
+                    light_off(&w);
                 }
                 else if *online == 0 {
-                    if let Ok(mut d) = DEV_MANAGER.lock() {
-                        d.restore_light();
-                    }
+                    restore_light(&w);
                 }
 
             } 
             true
         });
         let  proxy_idle = dbus_session.with_proxy("org.gnome.Mutter.IdleMonitor", "/org/gnome/Mutter/IdleMonitor/Core", time::Duration::from_millis(5000));
-        let _id = proxy_idle.match_signal(|h: dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitorWatchFired, _: &Connection, _: &Message| {
-            if let Ok(mut d) = DEV_MANAGER.lock() {
-                if d.idle_id == h.id {
-                    println!("idle trigger {:?}", h.id);
-                    d.light_off();
-                } else if d.active_id == h.id {
-                    println!("active trigger {:?}", h.id);
-                    d.restore_light();
-                }
+        let w = worker.clone();
+        let _id = proxy_idle.match_signal(move |h: dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitorWatchFired, _: &Connection, _: &Message| {
+            let (idle_id, active_id) = match IDLE.lock() {
+                Ok(idle) => (idle.idle_id, idle.active_id),
+                Err(_) => return true,
+            };
+            if idle_id == h.id {
+                println!("idle trigger {:?}", h.id);
+                light_off(&w);
+            } else if active_id == h.id {
+                println!("active trigger {:?}", h.id);
+                restore_light(&w);
             }
             true
         });
         let proxy = dbus_session.with_proxy("org.freedesktop.ScreenSaver", "/org/freedesktop/ScreenSaver", time::Duration::from_millis(5000));
-        let _id = proxy.match_signal(|h: screensaver::OrgFreedesktopScreenSaverActiveChanged, _: &Connection, _: &Message| {
+        let w = worker.clone();
+        let _id = proxy.match_signal(move |h: screensaver::OrgFreedesktopScreenSaverActiveChanged, _: &Connection, _: &Message| {
             println!("ActiveChanged {:?}", h.arg0);
-            if let Ok(mut d) = DEV_MANAGER.lock() {
-                if h.arg0 {
-                    d.light_off();
-                } else {
-                    d.restore_light();
-                }
+            if h.arg0 {
+                light_off(&w);
+            } else {
+                restore_light(&w);
             }
             true
         });
@@ -160,12 +220,12 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
         loop { 
             if let Ok(res) = dbus_session.process(time::Duration::from_millis(1000)) {
                 if res {
-                    if let Ok(mut d) = DEV_MANAGER.lock() {
-                        d.add_active_watch(&proxy_idle);
+                    if let Ok(mut idle) = IDLE.lock() {
+                        idle.add_active_watch(&proxy_idle);
                     }
                 }
-                if let Ok(mut d) = DEV_MANAGER.lock() {
-                    d.add_idle_watch(&proxy_idle);
+                if let Ok(mut idle) = IDLE.lock() {
+                    idle.add_idle_watch(&proxy_idle);
                 }
             }
         }
@@ -173,7 +233,21 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
     })
 }
 
-fn start_battery_monitor_task() -> JoinHandle<()> {
+fn light_off(worker: &worker::DeviceWorker) {
+    if let Ok(mut idle) = IDLE.lock() {
+        idle.light_off();
+    }
+    worker.send(worker::Job::Screensaver(true));
+}
+
+fn restore_light(worker: &worker::DeviceWorker) {
+    if let Ok(mut idle) = IDLE.lock() {
+        idle.restore_light();
+    }
+    worker.send(worker::Job::Screensaver(false));
+}
+
+fn start_battery_monitor_task(worker: worker::DeviceWorker) -> JoinHandle<()> {
     thread::spawn(move || {
         let dbus_system = Connection::new_system()
             .expect("should be able to connect to D-Bus system bus");
@@ -197,13 +271,12 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
             time::Duration::from_millis(5000)
         );
 
-        let _id = proxy_ac.match_signal(|h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+        let w = worker.clone();
+        let _id = proxy_ac.match_signal(move |h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
             let online: Option<&bool> = arg::prop_cast(&h.changed_properties, "Online");
             if let Some(online) = online {
                 info!("AC0 online: {:?}", online);
-                if let Ok(mut d) = DEV_MANAGER.lock() {
-                    d.set_ac_state(*online);
-                }
+                w.send(worker::Job::SetAcState(*online));
             }
             true
         });
@@ -212,19 +285,20 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
             let perc: Option<&f64> = arg::prop_cast(&h.changed_properties, "Percentage");
             if let Some(perc) = perc {
                 info!("Battery percentage: {:.1}", perc);
+                EVENTS.publish(comms::DaemonEvent::Battery { percentage: *perc });
             }
             true
         });
 
-        let _id = proxy_login.match_signal(|h: login1::OrgFreedesktopLogin1ManagerPrepareForSleep, _: &Connection, _: &Message| {
+        let w = worker.clone();
+        let _id = proxy_login.match_signal(move |h: login1::OrgFreedesktopLogin1ManagerPrepareForSleep, _: &Connection, _: &Message| {
             info!("PrepareForSleep {:?}", h.start);
-            if let Ok(mut d) = DEV_MANAGER.lock() {
-                d.set_ac_state_get();
-                if h.start {
-                    d.light_off();
-                } else {
-                    d.restore_light();
-                }
+            EVENTS.publish(comms::DaemonEvent::Sleep { start: h.start });
+            w.send(worker::Job::RefreshAcState);
+            if h.start {
+                light_off(&w);
+            } else {
+                restore_light(&w);
             }
             true
         });
@@ -233,6 +307,55 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
     })
 }
 
+/// Tells the device thread about Razer hidraw nodes coming and going
+fn start_hotplug_monitor_task(worker: worker::DeviceWorker) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let socket = match udev::MonitorBuilder::new()
+            .and_then(|builder| builder.match_subsystem("hidraw"))
+            .and_then(|builder| builder.listen()) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Could not monitor udev, hotplug is disabled: {}", e);
+                return;
+            }
+        };
+        info!("Monitoring udev for hidraw devices");
+
+        loop {
+            // The socket is non-blocking
+            for event in socket.iter() {
+                let path = match event.devnode() {
+                    Some(path) => path.to_string_lossy().into_owned(),
+                    None => continue,
+                };
+                match event.event_type() {
+                    udev::EventType::Add if is_razer_hid(&event.device()) => {
+                        info!("Razer hidraw node added: {}", path);
+                        worker.send(worker::Job::DeviceAdded);
+                    },
+                    // The parents are often gone already, the device thread
+                    // checks whether it was ours
+                    udev::EventType::Remove => worker.send(worker::Job::DeviceRemoved(path)),
+                    _ => {},
+                }
+            }
+            thread::sleep(time::Duration::from_millis(500));
+        }
+    })
+}
+
+/// HID_ID of the parent HID device looks like 0003:00001532:0000029F
+fn is_razer_hid(device: &udev::Device) -> bool {
+    let hid_id = device.parent_with_subsystem("hid").ok().flatten()
+        .and_then(|hid| hid.property_value("HID_ID").map(|id| id.to_string_lossy().into_owned()));
+    match hid_id {
+        Some(hid_id) => hid_id.split(':').nth(1)
+            .and_then(|vid| u32::from_str_radix(vid, 16).ok())
+            .map_or(false, |vid| vid == device::RAZER_VENDOR_ID as u32),
+        None => false,
+    }
+}
+
 /// Monitors signals and stops the daemon when receiving one
 pub fn start_shutdown_task() -> JoinHandle<()> {
     thread::spawn(|| {
@@ -241,71 +364,341 @@ pub fn start_shutdown_task() -> JoinHandle<()> {
         
         // If we reach this point, we have a signal and it is time to exit
         println!("Received signal, cleaning up");
-        if std::fs::metadata(comms::SOCKET_PATH).is_ok() {
-            std::fs::remove_file(comms::SOCKET_PATH).unwrap();
-        }
+        remove_sockets();
         std::process::exit(0);
     })
 }
 
-fn handle_data(mut stream: UnixStream) {
-    let mut buffer = [0u8; 4096];
-    if stream.read(&mut buffer).is_err() {
-        return;
-    }
-
-    if let Some(cmd) = comms::read_from_socket_req(&buffer) {
-        if let Some(s) = process_client_request(cmd) {
-            if let Ok(x) = bincode::serialize(&s) {
-                let result = stream.write_all(&x);
+/// How the clients of a socket encode their messages
+#[derive(Clone, Copy)]
+enum Protocol {
+    /// Length prefixed bincode frames, see `comms::write_frame`
+    Bincode,
+    /// One JSON document per line, for scripts (`socat`, ...)
+    Json,
+}
 
-                if let Err(error) = result {
-                    println!("Client disconnected with error: {error}");
+impl Protocol {
+    /// Reads the next message, None once the client is done
+    fn read(self, reader: &mut BufReader<UnixStream>) -> std::io::Result<Option<Vec<u8>>> {
+        match self {
+            Protocol::Bincode => match comms::read_frame(reader) {
+                Ok(request) => return Ok(Some(request)),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            },
+            Protocol::Json => loop {
+                let mut line = String::new();
+                // Same limit as for the frames
+                let read = reader.by_ref().take(comms::MAX_FRAME_SIZE as u64 + 1).read_line(&mut line)?;
+                if read == 0 {
+                    return Ok(None);
                 }
-            }
+                if read > comms::MAX_FRAME_SIZE {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "line too long"));
+                }
+                if !line.trim().is_empty() {
+                    return Ok(Some(line.into_bytes()));
+                }
+            },
         }
     }
-}
 
-pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
-    if let Ok(mut d) = DEV_MANAGER.lock() {
-        return match cmd {
-            comms::DaemonCommand::SetPowerMode { ac, pwr, cpu, gpu } => {
-                Some(comms::DaemonResponse::SetPowerMode { result: d.set_power_mode(ac, pwr, cpu, gpu) })
+    fn decode(self, request: &[u8]) -> Result<comms::DaemonCommand, comms::DeviceError> {
+        match self {
+            Protocol::Bincode => {
+                return comms::read_from_socket_req(request).ok_or(comms::DeviceError::UnknownCommand);
             },
-            comms::DaemonCommand::SetFanSpeed { ac, rpm } => {
-                Some(comms::DaemonResponse::SetFanSpeed { result: d.set_fan_rpm(ac, rpm) })
+            Protocol::Json => match serde_json::from_slice::<serde_json::Value>(request) {
+                Ok(value) => return decode_json(value),
+                Err(e) => return Err(comms::DeviceError::InvalidArgument(e.to_string())),
             },
-            comms::DaemonCommand::SetIdle { ac, val } => {
-                Some(comms::DaemonResponse::SetIdle { result: d.change_idle(ac, val) })
-            }
-            comms::DaemonCommand::GetFanSpeed{ac} => Some(comms::DaemonResponse::GetFanSpeed { rpm: d.get_fan_rpm(ac)}),
-            comms::DaemonCommand::GetPwrLevel{ac} => Some(comms::DaemonResponse::GetPwrLevel { pwr: d.get_power_mode(ac) }),
-            comms::DaemonCommand::GetCPUBoost{ac} => Some(comms::DaemonResponse::GetCPUBoost { cpu: d.get_cpu_boost(ac) }),
-            comms::DaemonCommand::GetGPUBoost{ac} => Some(comms::DaemonResponse::GetGPUBoost { gpu: d.get_gpu_boost(ac) }),
-            comms::DaemonCommand::SetBatteryHealthOptimizer { is_on, threshold } => { 
-                return Some(comms::DaemonResponse::SetBatteryHealthOptimizer { result: d.set_bho_handler(is_on, threshold)});
-            }
-            comms::DaemonCommand::GetBatteryHealthOptimizer() => {
-                return d.get_bho_handler().map(|result| 
-                    comms::DaemonResponse::GetBatteryHealthOptimizer {
-                        is_on: (result.0), 
-                        threshold: (result.1) 
+        }
+    }
+
+    fn write(self, stream: &mut UnixStream, response: &comms::DaemonResponse) -> std::io::Result<()> {
+        match self {
+            Protocol::Bincode => {
+                let encoded = bincode::serialize(response)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                return comms::write_frame(stream, &encoded);
+            },
+            Protocol::Json => {
+                let mut encoded = serde_json::to_vec(response)?;
+                encoded.push(b'\n');
+                return stream.write_all(&encoded);
+            },
+        }
+    }
+}
+
+/// A place in `CLIENTS`, given back when dropped
+struct ClientSlot;
+
+impl ClientSlot {
+    fn take() -> Option<ClientSlot> {
+        if CLIENTS.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+            CLIENTS.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        return Some(ClientSlot);
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        CLIENTS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Decodes a JSON command: a plain string for the commands without fields,
+/// otherwise an object with the command name as its only key
+fn decode_json(value: serde_json::Value) -> Result<comms::DaemonCommand, comms::DeviceError> {
+    use serde_json::Value;
+    use std::convert::TryFrom;
+    let invalid = |e: String| comms::DeviceError::InvalidArgument(e);
+    let name = match &value {
+        Value::String(name) => name.clone(),
+        Value::Object(fields) if fields.len() == 1 => fields.keys().next().cloned().unwrap_or_default(),
+        _ => return Err(invalid("expected a command name, or an object with the command name as its only key".to_string())),
+    };
+    // Likely a command of a newer daemon
+    if !comms::COMMANDS.contains(&name.as_str()) {
+        return Err(comms::DeviceError::UnknownCommand);
+    }
+
+    match value {
+        // Decoded here so the wrapped command gets the same treatment
+        Value::Object(mut fields) if name == "Request" => {
+            let mut request = fields.remove(&name).unwrap_or_default();
+            let id = request.get("id")
+                .and_then(Value::as_u64)
+                .and_then(|id| u32::try_from(id).ok())
+                .ok_or_else(|| invalid("Request needs an id between 0 and 4294967295".to_string()))?;
+            let command = match request.get_mut("command") {
+                Some(command) => decode_json(command.take())?,
+                None => return Err(invalid("Request needs a command".to_string())),
+            };
+            return Ok(comms::DaemonCommand::Request { id, command: Box::new(command) });
+        },
+        // `GetBatteryHealthOptimizer ()` is an empty tuple, which serde wants as
+        // `{"GetBatteryHealthOptimizer":[]}`
+        Value::String(name) => {
+            return command_from_json(&Value::String(name.clone()))
+                .or_else(|e| command_from_json(&serde_json::json!({ name: [] })).map_err(|_| e));
+        },
+        value => return command_from_json(&value),
+    }
+}
+
+/// `serde_json::from_value` takes `[]` for a unit, going through the text
+/// keeps the empty tuple working
+fn command_from_json(value: &serde_json::Value) -> Result<comms::DaemonCommand, comms::DeviceError> {
+    return serde_json::from_str(&value.to_string())
+        .map_err(|e| comms::DeviceError::InvalidArgument(e.to_string()));
+}
+
+/// Gives every client of `listener` a thread of its own, up to `MAX_CLIENTS`
+fn serve(listener: UnixListener, protocol: Protocol, worker: worker::DeviceWorker) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let slot = match ClientSlot::take() {
+                    Some(slot) => slot,
+                    None => {
+                        warn!("{} clients connected already, dropping a new one", MAX_CLIENTS);
+                        continue;
                     }
-                );
-            }
-            comms::DaemonCommand::GetDeviceName => {
-                let name = match &d.device {
-                    Some(device) => device.get_name(),
-                    None => "Unknown Device".into()
                 };
-                return Some(comms::DaemonResponse::GetDeviceName { name });
+                let worker = worker.clone();
+                thread::spawn(move || {
+                    let _slot = slot;
+                    handle_data(stream, protocol, &worker);
+                });
+            },
+            Err(_) => {} // Don't care about this
+        }
+    }
+}
+
+/// Serves a client until it hangs up. Requests are answered in order, a
+/// `Request` envelope gets its reply in a `Reply` with the same id.
+fn handle_data(mut stream: UnixStream, protocol: Protocol, worker: &worker::DeviceWorker) {
+    let peer = match access::Peer::of(&stream) {
+        Ok(peer) => peer,
+        Err(e) => {
+            warn!("Could not get the credentials of a client, dropping it: {e}");
+            return;
+        }
+    };
+    // A client that stops talking only ties up its own thread, and not for long
+    if let Err(e) = stream.set_read_timeout(Some(CLIENT_TIMEOUT)) {
+        warn!("Could not set the client timeout: {e}");
+    }
+    let mut reader = match stream.try_clone() {
+        Ok(read_half) => BufReader::new(read_half),
+        Err(e) => {
+            warn!("Could not set up the client connection: {e}");
+            return;
+        }
+    };
+
+    loop {
+        let request = match protocol.read(&mut reader) {
+            Ok(Some(request)) => request,
+            // The client is done
+            Ok(None) => return,
+            Err(e) => {
+                println!("Could not read the request: {e}");
+                return;
             }
+        };
 
+        let (id, cmd) = match protocol.decode(&request) {
+            Ok(comms::DaemonCommand::Request { id, command }) => (Some(id), Ok(*command)),
+            cmd => (None, cmd),
         };
-    } else {
-        return None;
+        if let Ok(comms::DaemonCommand::Subscribe) = cmd {
+            if access::check(&ACCESS, &peer, &comms::DaemonCommand::Subscribe).is_ok() {
+                stream_events(stream, protocol);
+                return;
+            }
+        }
+
+        let response = match answer(&peer, cmd, worker) {
+            Some(response) => response,
+            None => return,
+        };
+        let response = match id {
+            Some(id) => comms::DaemonResponse::Reply { id, response: Box::new(response) },
+            None => response,
+        };
+        if let Err(error) = protocol.write(&mut stream, &response) {
+            println!("Client disconnected with error: {error}");
+            return;
+        }
     }
 }
 
+/// Runs a client command if the access policy allows it
+fn answer(
+    peer: &access::Peer,
+    cmd: Result<comms::DaemonCommand, comms::DeviceError>,
+    worker: &worker::DeviceWorker,
+) -> Option<comms::DaemonResponse> {
+    // A newer client may send commands we don't know
+    let cmd = match cmd {
+        Ok(cmd) => cmd,
+        Err(error) => return Some(comms::DaemonResponse::Error { error }),
+    };
+    if let Err(reason) = access::check(&ACCESS, peer, &cmd) {
+        warn!("Refused {:?} from pid {}: {}", cmd, peer.pid, reason);
+        return Some(comms::DaemonResponse::Error { error: comms::DeviceError::PermissionDenied(reason) });
+    }
+    return worker.request(cmd);
+}
+
+/// Pushes the daemon events to a subscribed client until it goes away
+fn stream_events(mut stream: UnixStream, protocol: Protocol) {
+    let events = EVENTS.subscribe();
+    if protocol.write(&mut stream, &comms::DaemonResponse::Subscribed).is_err() {
+        return;
+    }
+    info!("Client subscribed to events");
+    loop {
+        match events.recv_timeout(HANGUP_CHECK) {
+            Ok(event) => {
+                if protocol.write(&mut stream, &comms::DaemonResponse::Event { event }).is_err() {
+                    info!("Subscribed client went away");
+                    return;
+                }
+            },
+            // Without events nothing is written, so look for the hangup
+            Err(RecvTimeoutError::Timeout) => {
+                if hung_up(&stream) {
+                    info!("Subscribed client went away");
+                    return;
+                }
+            },
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Whether the client closed its end. Subscribers have nothing more to
+/// send, whatever they do is read and dropped so it can't hide the hangup.
+fn hung_up(stream: &UnixStream) -> bool {
+    use std::os::unix::io::AsRawFd;
+    let mut buf = [0u8; 256];
+    loop {
+        let ret = unsafe {
+            libc::recv(stream.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), libc::MSG_DONTWAIT)
+        };
+        if ret < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return error.kind() != std::io::ErrorKind::WouldBlock;
+        }
+        if ret == 0 {
+            return true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn subscriber_input_does_not_hide_the_hangup() {
+        let (daemon, mut client) = UnixStream::pair().unwrap();
+        client.write_all(b"x").unwrap();
+        assert!(!hung_up(&daemon));
+        client.write_all(b"y").unwrap();
+        drop(client);
+        assert!(hung_up(&daemon));
+    }
+
+    fn json(text: &str) -> Result<comms::DaemonCommand, comms::DeviceError> {
+        return decode_json(serde_json::from_str(text).unwrap());
+    }
+
+    #[test]
+    fn json_plain_string_command() {
+        assert!(matches!(json(r#""GetDeviceName""#), Ok(comms::DaemonCommand::GetDeviceName)));
+    }
+
+    #[test]
+    fn json_unit_tuple_command() {
+        assert!(matches!(json(r#""GetBatteryHealthOptimizer""#), Ok(comms::DaemonCommand::GetBatteryHealthOptimizer())));
+        assert!(matches!(json(r#"{"GetBatteryHealthOptimizer":[]}"#), Ok(comms::DaemonCommand::GetBatteryHealthOptimizer())));
+    }
+
+    #[test]
+    fn json_command_with_fields() {
+        let command = json(r#"{"Request":{"id":7,"command":{"SetPowerMode":{"ac":1,"pwr":4,"cpu":2,"gpu":1}}}}"#);
+        match command {
+            Ok(comms::DaemonCommand::Request { id: 7, command }) => {
+                assert!(matches!(*command, comms::DaemonCommand::SetPowerMode { ac: 1, pwr: 4, cpu: 2, gpu: 1 }));
+            },
+            _ => panic!("not decoded: {:?}", command.err()),
+        }
+    }
 
+    #[test]
+    fn json_unknown_command() {
+        assert!(matches!(json(r#""Reboot""#), Err(comms::DeviceError::UnknownCommand)));
+        assert!(matches!(json(r#"{"Reboot":{"now":true}}"#), Err(comms::DeviceError::UnknownCommand)));
+    }
+
+    #[test]
+    fn json_invalid_argument() {
+        assert!(matches!(json(r#"{"SetPowerMode":{"ac":1,"pwr":"gaming","cpu":0,"gpu":0}}"#), Err(comms::DeviceError::InvalidArgument(_))));
+        assert!(matches!(json(r#"{"SetFanSpeed":{"ac":1}}"#), Err(comms::DeviceError::InvalidArgument(_))));
+        assert!(matches!(json(r#""SetFanSpeed""#), Err(comms::DeviceError::InvalidArgument(_))));
+        assert!(matches!(json("[1, 2]"), Err(comms::DeviceError::InvalidArgument(_))));
+    }
+}
@@ -1,149 +1,67 @@
 // mod kbd;
-use serde::{Deserialize, Serialize};
-use serde_big_array::BigArray;
-use std::{thread, time, io, fs};
+use std::{thread, time, io};
 use hidapi::HidApi;
-use crate::dbus_mutter_idlemonitor;
 use crate::config;
 use crate::battery;
+use crate::capture;
+use crate::comms::DeviceError;
+use crate::command::{BoostTarget, EcCommand, EcResponse};
+use crate::packet::{self, RazerPacket};
+use crate::transport::{DynTransport, SocketTransport, Transport};
 use dbus::blocking::Connection;
-
-const RAZER_VENDOR_ID: u16 = 0x1532;
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SupportedDevice {
-    pub name: String,
-    pub vid: String,
-    pub pid: String,
-    pub features: Vec<String>,
-    pub fan: Vec<u16>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RazerPacket {
-    report: u8,
-    status: u8,
-    id: u8,
-    remaining_packets: u16,
-    protocol_type: u8,
-    data_size: u8,
-    command_class: u8,
-    command_id: u8,
-    #[serde(with = "BigArray")]
-    args: [u8; 80],
-    crc: u8,
-    reserved: u8,
-}
-
-impl RazerPacket {
-// Command status
-    const RAZER_CMD_NEW:u8 = 0x00;
-    // const RAZER_CMD_BUSY:u8 = 0x01;
-    const RAZER_CMD_SUCCESSFUL:u8 = 0x02;
-    // const RAZER_CMD_FAILURE:u8 = 0x03;
-    // const RAZER_CMD_TIMEOUT:u8 =0x04;
-    const RAZER_CMD_NOT_SUPPORTED:u8 = 0x05;
-
-    fn new(command_class: u8, command_id: u8, data_size: u8) -> RazerPacket {
-        return RazerPacket {
-            report: 0x00,
-            status: RazerPacket::RAZER_CMD_NEW,
-            id: 0x1F,
-            remaining_packets: 0x0000,
-            protocol_type: 0x00,
-            data_size,
-            command_class,
-            command_id,
-            args: [0x00; 80],
-            crc: 0x00,
-            reserved: 0x00,
-        };
-    }
-
-    fn calc_crc(&mut self) -> Vec<u8>{
-        let mut res: u8 = 0x00;
-        let buf: Vec<u8> = bincode::serialize(self).unwrap();
-        for i in 2..88 {
-            res ^= buf[i];
-        }
-
-        self.crc = res;
-        return buf;
-    }
-}
+use service::{DeviceInfo, DmiInfo, Limits, SupportedDevice};
+
+pub const RAZER_VENDOR_ID: u16 = 0x1532;
+/// When set, the daemon drives the `razer-sim` listening on this socket
+pub const SIM_SOCKET_ENV: &str = "RAZER_LAPTOP_CONTROL_SIM";
+/// When set, reports carry their checksum. The daemon always sent 0 there,
+/// which the known ECs accept; filling it in is not confirmed on hardware yet.
+pub const SEND_CRC_ENV: &str = "RAZER_LAPTOP_CONTROL_SEND_CRC";
+/// How many times a setting is sent before giving up on reading it back
+const VERIFY_ATTEMPTS: u8 = 2;
+/// Read-only queries telling whether the EC knows a feature. When the last
+/// field is set a successful answer proves the feature, otherwise the probe
+/// can only rule it out (e.g. any EC reports the CPU boost, few accept boost 3).
+pub const FEATURE_PROBES: [(&str, EcCommand, bool); 2] = [
+    ("bho", EcCommand::GetBho, true),
+    ("boost", EcCommand::GetBoost { target: BoostTarget::Cpu }, false),
+];
 
 // Removed: now uses service::get_device_file_path()
 pub struct DeviceManager {
-    pub device: Option <RazerLaptop>,
+    pub device: Option <RazerLaptop<DynTransport>>,
+    device_path: Option<String>, // hidraw node of `device`, to notice its removal
+    ac_online: bool, // last known AC state, applied again when the device comes back
     supported_devices: Vec<SupportedDevice>,
+    dmi: DmiInfo, // tells apart the entries sharing a PID
     pub config: Option <config::Configuration>,
-    pub idle_id: u32,
-    pub active_id: u32,
-    add_active: bool,
-    pub change_idle: bool,
+    pub change_idle: bool, // the idle timeout in use changed
 }
 
 impl DeviceManager {
     pub fn new () -> DeviceManager {
         return DeviceManager {
             device: None,
+            device_path: None,
+            ac_online: false,
             supported_devices: vec![],
+            dmi: DmiInfo::from_env(),
             config: None,
-            idle_id: 0,
-            active_id: 0,
-            add_active: false,
             change_idle: false,
         };
     }
 
-    pub fn add_idle_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
-        if self.change_idle {
-            let mut timeout: u64 = 0;
-            let mut state: usize = 0;
-            if let Some(laptop) = self.get_device() {
-                state = laptop.get_ac_state();
-            }
-            if let Some(config) = self.get_config() {
-                timeout = config.power[state].idle as u64 * 60 * 1000; // idle is in minutes timeout is in miliseconds
-            }
-            if timeout != 0 {
-                if self.idle_id != 0 {
-                    self.remove_watch(proxy_idle);
-                }
-                if let Ok(id) = proxy_idle.add_idle_watch(timeout) {
-                    println!("idle handler {:?}", id);
-                    self.idle_id = id;
-                }
-            } else {
-                if self.idle_id != 0 {
-                    self.remove_watch(proxy_idle);
-                }
-            }
-            self.change_idle = false;
-        }
-    }
-
-
-
-    fn remove_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
-        if let Ok(_) = proxy_idle.remove_watch(self.idle_id) {
-            println!("remove idle handler");
+    pub fn read_laptops_file() -> io::Result<DeviceManager > {
+        let database = service::DeviceDatabase::load();
+        for error in &database.errors {
+            eprintln!("Skipping {}", error);
         }
-    }
-
-    pub fn add_active_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
-        if self.add_active {
-            if let Ok(id) = proxy_idle.add_user_active_watch() {
-                println!("active handler {:?}", id);
-                self.active_id = id;
-            }
+        if database.files.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no device database found"));
         }
-    }
-
-    pub fn read_laptops_file() -> io::Result<DeviceManager > {
-        let str: Vec<u8> = fs::read(service::get_device_file_path())?;
+        println!("device database files: {:?}", database.files);
         let mut res: DeviceManager = DeviceManager::new();
-        res.supported_devices = serde_json::from_slice(str.as_slice())?;
+        res.supported_devices = database.devices;
         println!("suported devices found: {:?}", res.supported_devices.len());
         match config::Configuration::read_from_config() {
             Ok(c) => res.config = Some(c),
@@ -161,24 +79,24 @@ impl DeviceManager {
         return None;
     }
 
-    pub fn light_off(&mut self) {
-        if self.idle_id != 0 {
-            self.add_active = true;
-        }
+    pub fn set_screensaver(&mut self, active: bool) {
         if let Some(laptop) = self.get_device() {
-            laptop.set_screensaver(true);
+            laptop.set_screensaver(active);
         }
     }
 
-    pub fn restore_light(&mut self) {
-        self.add_active = false;
+    /// Idle timeout in minutes for the current AC state
+    pub fn idle_timeout(&mut self) -> u32 {
+        let mut state: usize = 0;
         if let Some(laptop) = self.get_device() {
-            laptop.set_screensaver(false);
+            state = laptop.get_ac_state();
         }
+        return self.get_ac_config(state).map_or(0, |config| config.idle);
     }
 
 
-    pub fn change_idle(&mut self, ac: usize, timeout: u32) -> bool {
+    pub fn change_idle(&mut self, ac: usize, timeout: u32) -> Result<(), DeviceError> {
+        check_ac(ac)?;
         // let mut arm: bool = false;
         if let Some(config) = self.get_config() {
             if config.power[ac].idle != timeout {
@@ -191,11 +109,18 @@ impl DeviceManager {
             }
         }
 
-        return true;
+        return Ok(());
+    }
+
+    /// Checks done by `set_power_mode` before anything is saved or sent
+    pub fn validate_power_mode(&mut self, ac: usize, pwr: u8, cpu: u8, gpu: u8) -> Result<(), DeviceError> {
+        check_ac(ac)?;
+        // Don't save what the laptop would reject later
+        return check_power_mode(&self.limits(), pwr, cpu, gpu);
     }
 
-    pub fn set_power_mode(&mut self, ac: usize, pwr: u8, cpu: u8, gpu: u8) -> bool {
-        let mut res: bool = false;
+    pub fn set_power_mode(&mut self, ac: usize, pwr: u8, cpu: u8, gpu: u8) -> Result<(), DeviceError> {
+        self.validate_power_mode(ac, pwr, cpu, gpu)?;
         if let Some(config) = self.get_config() {
             config.power[ac].power_mode = pwr;
             config.power[ac].cpu_boost = cpu;
@@ -204,59 +129,109 @@ impl DeviceManager {
                 eprintln!("Error write config {:?}", e);
             }
         }
-        if let Some(laptop) = self.get_device() {
-            let state = laptop.get_ac_state();
-            if state != ac {
-                res = true;
-            } else {
-                res = laptop.set_power_mode(pwr, cpu, gpu);
-            }
+        let laptop = self.get_device().ok_or(DeviceError::NoDevice)?;
+        if laptop.get_ac_state() != ac {
+            // Saved, it will be applied when the AC state changes
+            return Ok(());
         }
 
-        return res;
+        return laptop.set_power_mode(pwr, cpu, gpu);
     }
 
 
-    pub fn set_fan_rpm(&mut self, ac:usize, rpm: i32) -> bool {
-        let mut res: bool = false;
+    /// Checks done by `set_fan_rpm` before anything is saved or sent
+    pub fn validate_fan_rpm(&self, ac: usize, rpm: i32) -> Result<(), DeviceError> {
+        check_ac(ac)?;
+        return check_fan_rpm(rpm);
+    }
+
+    pub fn set_fan_rpm(&mut self, ac:usize, rpm: i32) -> Result<(), DeviceError> {
+        self.validate_fan_rpm(ac, rpm)?;
         if let Some(config) = self.get_config() {
             config.power[ac].fan_rpm = rpm;
+            config.power[ac].zone_fan_rpm = None;
             if let Err(e) = config.write_to_file() {
                 eprintln!("Error write config {:?}", e);
             }
         }
              
-        if let Some(laptop) = self.get_device() {
-            let state = laptop.get_ac_state();
-            if state != ac {
-                res = true;
-            } else {
-                res = laptop.set_fan_rpm(rpm as u16);
-            }
+        let laptop = self.get_device().ok_or(DeviceError::NoDevice)?;
+        if laptop.get_ac_state() != ac {
+            return Ok(());
         }
 
-        return res;
+        return laptop.set_fan_rpm(rpm as u16);
     }
 
+    /// Checks done by `set_zone_fan_rpm` before anything is saved or sent
+    pub fn validate_zone_fan_rpm(&mut self, ac: usize, zone: usize, rpm: i32) -> Result<(), DeviceError> {
+        check_ac(ac)?;
+        check_fan_rpm(rpm)?;
+        // The config keeps two zones, the attached model may have less
+        let zones = self.get_device().map_or(2, |laptop| laptop.fan_zone_count());
+        if zone >= zones.min(2) {
+            return Err(DeviceError::NotSupported);
+        }
+        return Ok(());
+    }
 
+    /// Sets the speed of a single fan zone (0 is the CPU fan, 1 the GPU one)
+    pub fn set_zone_fan_rpm(&mut self, ac: usize, zone: usize, rpm: i32) -> Result<(), DeviceError> {
+        self.validate_zone_fan_rpm(ac, zone, rpm)?;
+        // Saved even without a device, it is applied when one is attached
+        if let Some(config) = self.get_config() {
+            let power = &mut config.power[ac];
+            let mut zone_fan_rpm = [power.fan_rpm_for(0), power.fan_rpm_for(1)];
+            zone_fan_rpm[zone] = rpm;
+            power.zone_fan_rpm = Some(zone_fan_rpm);
+            if let Err(e) = config.write_to_file() {
+                eprintln!("Error write config {:?}", e);
+            }
+        }
 
+        let laptop = self.get_device().ok_or(DeviceError::NoDevice)?;
+        if laptop.get_ac_state() != ac {
+            return Ok(());
+        }
 
+        return laptop.set_zone_fan_rpm(zone, rpm as u16);
+    }
 
-    pub fn get_fan_rpm(&mut self, ac: usize) -> i32 {
+    pub fn get_zone_fan_rpm(&mut self, ac: usize, zone: usize) -> Result<i32, DeviceError> {
+        check_ac(ac)?;
         if let Some(laptop) = self.get_device() {
+            if zone >= laptop.fan_zone_count() {
+                return Err(DeviceError::NotSupported);
+            }
             if laptop.ac_state as usize == ac {
-                return laptop.get_fan_rpm() as i32;
+                return laptop.get_zone_fan_rpm(zone).map(|rpm| rpm as i32);
             }
         }
 
         if let Some(config) = self.get_ac_config(ac) {
-            return config.fan_rpm;
+            return Ok(config.fan_rpm_for(zone.min(1)));
         }
 
-        return 0;
+        return Err(DeviceError::NoDevice);
     }
 
-    pub fn get_power_mode(&mut self, ac:usize) -> u8 {
+    pub fn get_fan_rpm(&mut self, ac: usize) -> Result<i32, DeviceError> {
+        check_ac(ac)?;
+        if let Some(laptop) = self.get_device() {
+            if laptop.ac_state as usize == ac {
+                return laptop.get_fan_rpm().map(|rpm| rpm as i32);
+            }
+        }
+
+        if let Some(config) = self.get_ac_config(ac) {
+            return Ok(config.fan_rpm);
+        }
+
+        return Err(DeviceError::NoDevice);
+    }
+
+    pub fn get_power_mode(&mut self, ac: usize) -> Result<u8, DeviceError> {
+        check_ac(ac)?;
         if let Some(laptop) = self.get_device() {
             if laptop.ac_state as usize == ac {
                 return laptop.get_power_mode(0x01);
@@ -264,13 +239,14 @@ impl DeviceManager {
         }
 
         if let Some(config) = self.get_ac_config(ac) {
-            return config.power_mode;
+            return Ok(config.power_mode);
         }
 
-        return 0;
+        return Err(DeviceError::NoDevice);
     }
 
-    pub fn get_cpu_boost(&mut self, ac:usize) -> u8 {
+    pub fn get_cpu_boost(&mut self, ac: usize) -> Result<u8, DeviceError> {
+        check_ac(ac)?;
         if let Some(laptop) = self.get_device() {
             if laptop.ac_state as usize == ac {
                 return laptop.get_cpu_boost();
@@ -278,13 +254,14 @@ impl DeviceManager {
         }
 
         if let Some(config) = self.get_ac_config(ac) {
-            return config.cpu_boost;
+            return Ok(config.cpu_boost);
         }
 
-        return 0;
+        return Err(DeviceError::NoDevice);
     }
 
-    pub fn get_gpu_boost(&mut self, ac:usize) -> u8 {
+    pub fn get_gpu_boost(&mut self, ac: usize) -> Result<u8, DeviceError> {
+        check_ac(ac)?;
         if let Some(laptop) = self.get_device() {
             if laptop.ac_state as usize == ac {
                 return laptop.get_gpu_boost();
@@ -292,13 +269,14 @@ impl DeviceManager {
         }
 
         if let Some(config) = self.get_ac_config(ac) {
-            return config.gpu_boost;
+            return Ok(config.gpu_boost);
         }
 
-        return 0;
+        return Err(DeviceError::NoDevice);
     }
 
     pub fn set_ac_state(&mut self, ac: bool) {
+        self.ac_online = ac;
         if let Some(laptop) = self.get_device() {
             laptop.set_ac_state(ac);
         }
@@ -306,17 +284,24 @@ impl DeviceManager {
         let config: Option<config::PowerConfig> = self.get_ac_config(ac as usize);
         if let Some(config) = config {
             if let Some(laptop) = self.get_device() {
-                laptop.set_config(config);
+                if let Err(e) = laptop.set_config(config) {
+                    eprintln!("Error applying config: {}", e);
+                }
             }
         }
     }
 
+    pub fn ac_online(&self) -> bool {
+        return self.ac_online;
+    }
+
     pub fn set_ac_state_get(&mut self) {
         let dbus_system = Connection::new_system()
             .expect("failed to connect to D-Bus system bus");
         let proxy_ac = dbus_system.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/line_power_AC0", time::Duration::from_millis(5000));
         use battery::OrgFreedesktopUPowerDevice;
         if let Ok(online) = proxy_ac.online() {
+            self.ac_online = online;
             if let Some(laptop) = self.get_device() {
                 laptop.set_ac_state(online);
             }
@@ -324,27 +309,86 @@ impl DeviceManager {
             let config: Option<config::PowerConfig> = self.get_ac_config(online as usize);
             if let Some(config) = config {
                 if let Some(laptop) = self.get_device() {
-                    laptop.set_config(config);
+                    if let Err(e) = laptop.set_config(config) {
+                        eprintln!("Error applying config: {}", e);
+                    }
                 }
             }
         }
 
     }
 
-    pub fn get_device(&mut self) -> Option<&mut RazerLaptop> {
+    /// A Razer hidraw node showed up, attach it if we are waiting for a device
+    pub fn device_added(&mut self) {
+        if self.device.is_some() {
+            return;
+        }
+        self.discover_devices();
+        if let Some(laptop) = self.get_device() {
+            println!("supported device attached: {:?}", laptop.get_name());
+            laptop.probe_features();
+            laptop.read_identity();
+            // Re-applies the active PowerConfig
+            self.set_ac_state(self.ac_online);
+        }
+    }
+
+    /// A hidraw node went away, drop the device if it was ours
+    pub fn device_removed(&mut self, path: &str) {
+        if self.device_path.as_deref() == Some(path) {
+            println!("supported device removed: {}", path);
+            self.device = None;
+            self.device_path = None;
+        }
+    }
+
+    pub fn get_device(&mut self) -> Option<&mut RazerLaptop<DynTransport>> {
         return self.device.as_mut();
     }
 
-    pub fn set_bho_handler(&mut self, is_on: bool, threshold: u8) -> bool {
+    pub fn get_features(&mut self) -> Result<(Vec<String>, Vec<String>), DeviceError> {
         return self.get_device()
-            .map_or(false, |laptop| laptop.set_bho(is_on, threshold));
+            .ok_or(DeviceError::NoDevice)
+            .map(|laptop| (laptop.info.features.clone(), laptop.unsupported.clone()));
+    }
+
+    pub fn get_device_info(&mut self) -> Result<DeviceInfo, DeviceError> {
+        let dmi = self.dmi.clone();
+        let laptop = self.get_device().ok_or(DeviceError::NoDevice)?;
+        let (vid, pid) = laptop.info.ids().unwrap_or_default();
+        return Ok(DeviceInfo {
+            name: laptop.get_name(),
+            vid,
+            pid,
+            dmi,
+            firmware: laptop.firmware.clone(),
+            serial: laptop.serial.clone(),
+        });
     }
 
-    pub fn get_bho_handler(&mut self) -> Option<(bool, u8)> {
+    /// Limits of the attached model, the generic ones without a device
+    pub fn limits(&mut self) -> Limits {
+        return self.get_device().map_or_else(Limits::default, |laptop| laptop.limits());
+    }
+
+    /// Checks done by `set_bho_handler` before anything is sent
+    pub fn validate_bho(&mut self, threshold: u8) -> Result<(), DeviceError> {
         return self.get_device()
-            .and_then(|laptop| laptop.get_bho()
-            .map(|result| byte_to_bho(result)));
-    } 
+            .ok_or(DeviceError::NoDevice)
+            .and_then(|laptop| laptop.check_bho(threshold));
+    }
+
+    pub fn set_bho_handler(&mut self, is_on: bool, threshold: u8) -> Result<(), DeviceError> {
+        return self.get_device()
+            .ok_or(DeviceError::NoDevice)
+            .and_then(|laptop| laptop.set_bho(is_on, threshold));
+    }
+
+    pub fn get_bho_handler(&mut self) -> Result<(bool, u8), DeviceError> {
+        return self.get_device()
+            .ok_or(DeviceError::NoDevice)
+            .and_then(|laptop| laptop.get_bho());
+    }
 
     fn get_config(&mut  self) -> Option<&mut config::Configuration> {
         return self.config.as_mut();
@@ -371,23 +415,95 @@ impl DeviceManager {
     pub fn find_supported_device(&mut self, vid: u16, pid: u16) -> Option<&SupportedDevice> {
         println!("Looking for device: VID={:04x} PID={:04x}", vid, pid);
         println!("Supported devices count: {}", self.supported_devices.len());
-        
-        for device in &self.supported_devices {
-            let svid = u16::from_str_radix(&device.vid, 16).unwrap();
-            let spid = u16::from_str_radix(&device.pid, 16).unwrap();
-            println!("Checking against: {} VID={:04x} PID={:04x}", device.name, svid, spid);
-
-            if svid == vid && spid == pid {
-                println!("MATCH FOUND: {}", device.name);
+
+        // Entries with invalid ids were dropped when loading
+        let candidates: Vec<&SupportedDevice> = self.supported_devices.iter()
+            .filter(|device| device.ids() == Some((vid, pid)))
+            .collect();
+        // Several models may share the PID, the DMI product name tells them
+        // apart. Otherwise the first entry without `dmi`, or just the first.
+        let found = candidates.iter().find(|device| device.matches_dmi(&self.dmi))
+            .or_else(|| candidates.iter().find(|device| device.dmi.is_none()))
+            .or_else(|| candidates.first());
+        match found {
+            Some(device) => {
+                println!("MATCH FOUND: {} (DMI product name {:?})", device.name, self.dmi.product_name);
                 return Some(device);
+            },
+            None => {
+                println!("No match found");
+                return None;
             }
         }
-        
-        println!("No match found");
-        None
+    }
+
+    pub fn is_simulated(&self) -> bool {
+        return std::env::var(SIM_SOCKET_ENV).is_ok();
+    }
+
+    /// Attaches to a `razer-sim` instance instead of a hidraw node
+    fn attach_simulator(&mut self, path: &str) {
+        match SocketTransport::connect(path) {
+            Ok(transport) => {
+                println!("Connected to simulator at {}: VID={:04x} PID={:04x}", path, transport.vid, transport.pid);
+                let (vid, pid) = (transport.vid, transport.pid);
+                if let Some(supported_device) = self.find_supported_device(vid, pid) {
+                    self.device = Some(RazerLaptop::new(supported_device, Box::new(transport)));
+                }
+            },
+            Err(e) => {
+                eprintln!("Error connecting to simulator at {}: {}", path, e);
+            }
+        }
+    }
+
+    /// Opens the interface of a model that takes the EC feature reports.
+    ///
+    /// The keyboard and mouse endpoints share the PID, so unless laptops.json
+    /// pins the interface every one of them is sent a read-only query, lowest
+    /// interface number and Generic Desktop usage page first.
+    fn open_interface(&mut self, api: &HidApi, supported_device: &SupportedDevice, mut interfaces: Vec<&hidapi::DeviceInfo>) -> bool {
+        let pinned = supported_device.interface;
+        match pinned {
+            Some(interface) => interfaces.retain(|d| d.interface_number() == interface),
+            None => interfaces.sort_by_key(|d| (d.interface_number(), d.usage_page() != 0x0001)),
+        }
+        // hidapi lists every top level collection, they share the hidraw node
+        let mut tried: Vec<&std::ffi::CStr> = vec![];
+        for device in interfaces {
+            if tried.contains(&device.path()) {
+                continue;
+            }
+            tried.push(device.path());
+            let dev = match api.open_path(device.path()) {
+                Ok(dev) => dev,
+                Err(e) => {
+                    eprintln!("Error opening interface {}: {}", device.interface_number(), e);
+                    continue;
+                }
+            };
+
+            let mut laptop: RazerLaptop<DynTransport> = RazerLaptop::new(supported_device, Box::new(dev));
+            if pinned.is_none() && !laptop.responds() {
+                println!("Interface {} does not answer EC queries", device.interface_number());
+                continue;
+            }
+            println!("Using interface {} ({:?})", device.interface_number(), device.path());
+            self.device = Some(laptop);
+            self.device_path = Some(device.path().to_string_lossy().into_owned());
+            return true;
+        }
+
+        eprintln!("No interface of {} answers EC queries", supported_device.name);
+        return false;
     }
 
     pub fn discover_devices(&mut self)  {
+        if let Ok(path) = std::env::var(SIM_SOCKET_ENV) {
+            self.attach_simulator(&path);
+            return;
+        }
+
         // Check if socket is OK
         match HidApi::new() {
             Ok(api) => {
@@ -397,29 +513,36 @@ impl DeviceManager {
                 
                 let razer_devices: Vec<_> = all_devices.iter()
                     .filter(|d| d.vendor_id() == RAZER_VENDOR_ID)
+                    .copied()
                     .collect();
                 println!("Found {} Razer devices", razer_devices.len());
                 
-                for device in razer_devices {
-                    println!("Razer device: VID={:04x} PID={:04x} Interface={}", 
-                            device.vendor_id(), device.product_id(), device.interface_number());
-                    let result = self.find_supported_device(device.vendor_id(), device.product_id());
-                    if let Some(supported_device) = result {
-                        println!("Device matches supported list: {}", supported_device.name);
-                        match api.open_path(device.path()) {
-                            Ok(dev) => {
-                                self.device = Some(RazerLaptop::new(
-                                    supported_device.name.clone(),
-                                    supported_device.features.clone(),
-                                    supported_device.fan.clone(),
-                                    dev
-                                ));
-                                break;
-                            },
-                            Err(e) => {
-                                eprintln!("Error: {}", e);
-                            }
-                        };
+                for device in &razer_devices {
+                    println!("Razer device: VID={:04x} PID={:04x} Interface={} UsagePage={:04x} Usage={:04x}", 
+                            device.vendor_id(), device.product_id(), device.interface_number(),
+                            device.usage_page(), device.usage());
+                }
+
+                let mut models: Vec<(u16, u16)> = razer_devices.iter()
+                    .map(|d| (d.vendor_id(), d.product_id()))
+                    .collect();
+                models.sort();
+                models.dedup();
+                for (vid, pid) in models {
+                    let supported_device = match self.find_supported_device(vid, pid) {
+                        Some(supported_device) => supported_device.clone(),
+                        None => {
+                            println!("Unknown Razer device {:04x}:{:04x}, run `daemon --onboard` to propose a laptops.json entry for it", vid, pid);
+                            continue;
+                        },
+                    };
+                    println!("Device matches supported list: {}", supported_device.name);
+                    let interfaces: Vec<_> = razer_devices.iter()
+                        .filter(|d| d.vendor_id() == vid && d.product_id() == pid)
+                        .copied()
+                        .collect();
+                    if self.open_interface(&api, &supported_device, interfaces) {
+                        return;
                     }
                 }
             },
@@ -430,29 +553,40 @@ impl DeviceManager {
     }
 }
 
-pub struct RazerLaptop {
-    name: String,
-    features: Vec<String>,
-    fan: Vec<u16>,
-    device: hidapi::HidDevice,
+pub struct RazerLaptop<T: Transport = hidapi::HidDevice> {
+    info: SupportedDevice, // features corrected by probe_features
+    unsupported: Vec<String>, // probed features the EC answered not supported
+    firmware: Option<String>, // read by read_identity
+    serial: Option<String>,
+    fan: Vec<(u16, u16)>, // [min, max] of every independent fan zone
+    device: T,
     power: u8, // need for fan
-    fan_rpm: u8, // need for power
+    fan_rpm: [u8; 2], // need for power, one per EC zone
     ac_state: u8, // index config array
     screensaver: bool,
+    capture: Option<capture::Capture>, // set with RAZER_LAPTOP_CONTROL_CAPTURE
+    send_crc: bool, // set with RAZER_LAPTOP_CONTROL_SEND_CRC
 }
 //
-impl RazerLaptop {
+impl<T: Transport> RazerLaptop<T> {
 
-    pub fn new(name: String, features: Vec<String>, fan: Vec<u16>, device: hidapi::HidDevice) -> RazerLaptop {
+    pub fn new(info: &SupportedDevice, device: T) -> RazerLaptop<T> {
+        let fan = (0..info.fan_zone_count())
+            .map(|zone| info.fan_range(zone).unwrap_or((0, u16::MAX)))
+            .collect();
         return RazerLaptop{
-            name,
-            features,
+            info: info.clone(),
+            unsupported: vec![],
+            firmware: None,
+            serial: None,
             fan,
             device,
             power: 0,
-            fan_rpm: 0,
+            fan_rpm: [0, 0],
             ac_state: 0,
-            screensaver: false
+            screensaver: false,
+            capture: capture::Capture::from_env(),
+            send_crc: std::env::var_os(SEND_CRC_ENV).is_some(),
         };
     }
 
@@ -460,13 +594,22 @@ impl RazerLaptop {
         self.screensaver = active;
     }
 
-    pub fn set_config(&mut self, config: config::PowerConfig) -> bool {
-        let mut ret: bool = false;
-
-        ret |= self.set_power_mode(config.power_mode, config.cpu_boost, config.gpu_boost);
-        ret |= self.set_fan_rpm(config.fan_rpm as u16);
+    pub fn set_config(&mut self, config: config::PowerConfig) -> Result<(), DeviceError> {
+        // Configs saved before the model limits were enforced may be out of range
+        let limits = self.limits();
+        let cpu_boost = config.cpu_boost.min(limits.max_cpu_boost);
+        let gpu_boost = config.gpu_boost.min(limits.max_gpu_boost);
+        // Try both even if the first one fails, report the first error
+        let power = self.set_power_mode(config.power_mode, cpu_boost, gpu_boost);
+        let fan = match config.zone_fan_rpm {
+            Some(rpm) if self.fan_zone_count() > 1 => {
+                self.set_zone_fan_rpm(0, rpm[0] as u16)
+                    .and(self.set_zone_fan_rpm(1, rpm[1] as u16))
+            },
+            _ => self.set_fan_rpm(config.fan_rpm as u16),
+        };
 
-        return ret;
+        return power.and(fan);
     }
 
     pub fn set_ac_state(&mut self, online: bool) -> usize {
@@ -484,19 +627,96 @@ impl RazerLaptop {
     }
 
     pub fn get_name(&self) -> String {
-        return self.name.clone();
+        return self.info.name.clone();
     }
 
     pub fn have_feature(&mut self, fch: String) -> bool {
-        return self.features.contains(&fch);
+        return self.info.has_feature(&fch);
+    }
+
+    /// Asks the EC about the features listed in `FEATURE_PROBES` and fixes
+    /// up the ones laptops.json got wrong. Probes that fail for any other
+    /// reason than "not supported" keep the declared value.
+    pub fn probe_features(&mut self) {
+        for (feature, command, conclusive) in FEATURE_PROBES {
+            let declared = self.info.features.iter().any(|f| f == feature);
+            match self.execute(command) {
+                Ok(_) => {
+                    if conclusive && !declared {
+                        println!("Probe: the EC supports {} although it is not declared, enabling it", feature);
+                        self.info.features.push(feature.to_string());
+                    }
+                },
+                Err(DeviceError::NotSupported) => {
+                    if declared {
+                        println!("Probe: {} is declared but the EC does not support it, disabling it", feature);
+                        self.info.features.retain(|f| f != feature);
+                    }
+                    self.unsupported.push(feature.to_string());
+                },
+                Err(e) => {
+                    eprintln!("Probe: could not check {}, keeping the declared value: {}", feature, e);
+                },
+            }
+        }
+        println!("Effective features: {:?}", self.info.features);
+    }
+
+    /// Asks the EC for its firmware version and serial number, to tell
+    /// firmware revisions apart in bug reports
+    pub fn read_identity(&mut self) {
+        match self.execute(EcCommand::GetFirmwareVersion) {
+            Ok(EcResponse::FirmwareVersion { major, minor }) => self.firmware = Some(format!("v{}.{}", major, minor)),
+            Ok(_) => {},
+            Err(e) => eprintln!("Could not read the firmware version: {}", e),
+        }
+        match self.execute(EcCommand::GetSerial) {
+            Ok(EcResponse::Serial { serial }) => {
+                let serial: String = serial.iter()
+                    .take_while(|b| **b != 0)
+                    .map(|b| *b as char)
+                    .collect();
+                self.serial = Some(serial.trim().to_string());
+            },
+            Ok(_) => {},
+            Err(e) => eprintln!("Could not read the serial number: {}", e),
+        }
+        println!("Firmware {:?}, serial {:?}", self.firmware, self.serial);
+    }
+
+    /// Sends a command that only reads from the EC, anything else is refused
+    pub fn query(&mut self, command: EcCommand) -> Result<EcResponse, DeviceError> {
+        if !command.is_query() {
+            return Err(DeviceError::InvalidArgument(format!("{:?} is not a query", command)));
+        }
+        return self.execute(command);
+    }
+
+    /// Whether the EC answers a harmless query on this interface
+    pub fn responds(&mut self) -> bool {
+        return self.execute(EcCommand::GetPowerMode { zone: 0x01 }).is_ok();
+    }
+
+    pub fn limits(&self) -> Limits {
+        return self.info.limits();
+    }
+
+    pub fn fan_zone_count(&self) -> usize {
+        return self.fan.len();
+    }
+
+    /// Index of `fan_rpm` for an EC zone (0x01 or 0x02)
+    fn zone_index(zone: u8) -> usize {
+        return (zone as usize).saturating_sub(1).min(1);
     }
 
-    fn clamp_fan(&mut self, rpm: u16) -> u8 {
-        if rpm > self.fan[1] {
-            return (self.fan[1] / 100) as u8;
+    fn clamp_fan(&mut self, zone: usize, rpm: u16) -> u8 {
+        let (min, max) = *self.fan.get(zone).unwrap_or(&self.fan[0]);
+        if rpm > max {
+            return (max / 100) as u8;
         }
-        if rpm < self.fan[0] {
-            return (self.fan[0] / 100) as u8;
+        if rpm < min {
+            return (min / 100) as u8;
         }
 
         return (rpm / 100) as u8;
@@ -516,237 +736,443 @@ impl RazerLaptop {
 
 
 
-    pub fn get_power_mode(&mut self, zone: u8) -> u8 {
-        let mut report: RazerPacket = RazerPacket::new(0x0d, 0x82, 0x04);
-        report.args[0] = 0x00;
-        report.args[1] = zone;
-        report.args[2] = 0x00;
-        report.args[3] = 0x00;
-        if let Some(response) = self.send_report(report) {
-            return response.args[2];
-        }
-        return 0;
+    /// Sends a typed command and decodes the answer
+    pub fn execute(&mut self, command: EcCommand) -> Result<EcResponse, DeviceError> {
+        let request = command.encode().map_err(DeviceError::InvalidArgument)?;
+        return self.send_report(request)
+            .map(|response| command.decode_response(&response));
     }
 
-    fn set_power(&mut self, zone: u8) -> bool {
-        let mut report: RazerPacket = RazerPacket::new(0x0d, 0x02, 0x04);
-        report.args[0] = 0x00;
-        report.args[1] = zone;
-        report.args[2] = self.power;
-        match self.fan_rpm {
-            0 => report.args[3] = 0x00,
-            _ => report.args[3] = 0x01
+    pub fn get_power_mode(&mut self, zone: u8) -> Result<u8, DeviceError> {
+        match self.execute(EcCommand::GetPowerMode { zone })? {
+            EcResponse::PowerMode { mode, .. } => Ok(mode),
+            _ => Err(DeviceError::ResponseMismatch),
         }
-        if let Some(_) = self.send_report(report) {
-            return  true;
+    }
+
+    /// Sends a set command and reads the state back with the matching get
+    /// command, sending it again while they disagree.
+    fn set_verified(&mut self, command: EcCommand, read_back: EcCommand, applied: impl Fn(&EcResponse) -> bool) -> Result<(), DeviceError> {
+        let mut actual = EcResponse::Done;
+        for attempt in 0..VERIFY_ATTEMPTS {
+            self.execute(command)?;
+            actual = self.execute(read_back)?;
+            if applied(&actual) {
+                return Ok(());
+            }
+            eprintln!("{:?} not applied, device reports {:?} (attempt {})", command, actual, attempt + 1);
         }
 
-        return false;
+        return Err(DeviceError::Mismatch(format!("requested {:?}, device reports {:?}", command, actual)));
     }
 
-    pub fn get_cpu_boost(&mut self) -> u8 {
-        let mut report: RazerPacket = RazerPacket::new(0x0d, 0x87, 0x03);
-        report.args[0] = 0x00;
-        report.args[1] = 0x01;
-        report.args[2] = 0x00;
-        if let Some(response) = self.send_report(report) {
-            return response.args[2];
-        }
-        return 0;
+    fn set_power(&mut self, zone: u8) -> Result<(), DeviceError> {
+        let mode = self.power;
+        let manual_fan = self.fan_rpm[Self::zone_index(zone)] != 0;
+        let command = EcCommand::SetPowerMode { zone, mode, manual_fan };
+        return self.set_verified(command, EcCommand::GetPowerMode { zone },
+            |r| matches!(r, EcResponse::PowerMode { mode: m, .. } if *m == mode));
     }
 
-    fn set_cpu_boost(&mut self, mut boost: u8) -> bool {
-        let mut report: RazerPacket = RazerPacket::new(0x0d, 0x07, 0x03);
-        if boost == 3 && !self.have_feature("boost".to_string()) {
-            boost = 2;
-        }
-        report.args[0] = 0x00;
-        report.args[1] = 0x01;
-        report.args[2] = boost;
-        if let Some(_)= self.send_report(report) {
-            return true;
+    fn get_boost(&mut self, target: BoostTarget) -> Result<u8, DeviceError> {
+        match self.execute(EcCommand::GetBoost { target })? {
+            EcResponse::Boost { level, .. } => Ok(level),
+            _ => Err(DeviceError::ResponseMismatch),
         }
+    }
 
-        return false;
+    pub fn get_cpu_boost(&mut self) -> Result<u8, DeviceError> {
+        return self.get_boost(BoostTarget::Cpu);
     }
 
-    fn get_gpu_boost(&mut self) -> u8 {
-        let mut report: RazerPacket = RazerPacket::new(0x0d, 0x87, 0x03);
-        report.args[0] = 0x00;
-        report.args[1] = 0x02;
-        report.args[2] = 0x00;
-        if let Some(response) = self.send_report(report){
-            return response.args[2];
-        }
-        return 0;
+    fn set_cpu_boost(&mut self, boost: u8) -> Result<(), DeviceError> {
+        return self.set_boost(BoostTarget::Cpu, boost);
     }
 
-    fn set_gpu_boost(&mut self, boost: u8) -> bool {
-        let mut report: RazerPacket = RazerPacket::new(0x0d, 0x07, 0x03);
-        report.args[0] = 0x00;
-        report.args[1] = 0x02;
-        report.args[2] = boost;
-        if let Some(_) = self.send_report(report) {
-            return true;
-        }
-        return false;
+    fn set_boost(&mut self, target: BoostTarget, level: u8) -> Result<(), DeviceError> {
+        return self.set_verified(EcCommand::SetBoost { target, level }, EcCommand::GetBoost { target },
+            |r| matches!(r, EcResponse::Boost { level: l, .. } if *l == level));
     }
 
-    pub fn set_power_mode(&mut self, mode: u8, cpu_boost: u8, gpu_boost: u8) -> bool {
+    fn get_gpu_boost(&mut self) -> Result<u8, DeviceError> {
+        return self.get_boost(BoostTarget::Gpu);
+    }
+
+    fn set_gpu_boost(&mut self, boost: u8) -> Result<(), DeviceError> {
+        return self.set_boost(BoostTarget::Gpu, boost);
+    }
+
+    pub fn set_power_mode(&mut self, mode: u8, cpu_boost: u8, gpu_boost: u8) -> Result<(), DeviceError> {
+        check_power_mode(&self.limits(), mode, cpu_boost, gpu_boost)?;
         if mode <= 3 {
             self.power = mode;
-            self.set_power(0x01);
-            self.set_power(0x02);
+            self.set_power(0x01)?;
+            self.set_power(0x02)?;
         } else if mode == 4 {
             self.power =  mode;
-            self.fan_rpm = 0;
-            self.get_power_mode(0x01);
-            self.set_power(0x01);
-            self.get_cpu_boost();
-            self.set_cpu_boost(cpu_boost);
-            self.get_gpu_boost();
-            self.set_gpu_boost(gpu_boost);
-            self.get_power_mode(0x02);
-            self.set_power(0x02);
-        }
-
-        return true;
-    }
-
-    fn set_rpm(&mut self, zone: u8) -> bool {
-        let mut report:RazerPacket = RazerPacket::new(0x0d, 0x01, 0x03);
-        // Set fan RPM
-        report.args[0] = 0x00;
-        report.args[1] = zone;
-        report.args[2] = self.fan_rpm;
-        if let Some(_) = self.send_report(report) {
-            return true;
+            self.fan_rpm = [0, 0];
+            self.get_power_mode(0x01)?;
+            self.set_power(0x01)?;
+            self.get_cpu_boost()?;
+            self.set_cpu_boost(cpu_boost)?;
+            self.get_gpu_boost()?;
+            self.set_gpu_boost(gpu_boost)?;
+            self.get_power_mode(0x02)?;
+            self.set_power(0x02)?;
+        } else {
+            return Err(DeviceError::InvalidArgument(format!("unknown power mode {}", mode)));
         }
 
-        return false;
+        return Ok(());
     }
 
-    pub fn set_fan_rpm(&mut self, value: u16) -> bool {
+    fn set_rpm(&mut self, zone: u8) -> Result<(), DeviceError> {
+        let rpm = self.fan_rpm[Self::zone_index(zone)] as u16 * 100;
+        return self.set_verified(EcCommand::SetFanRpm { zone, rpm }, EcCommand::GetFanRpm { zone },
+            |r| matches!(r, EcResponse::FanRpm { rpm: actual, .. } if *actual == rpm));
+    }
+
+    /// Applies `fan_rpm` of one EC zone
+    fn apply_fan(&mut self, zone: u8) -> Result<(), DeviceError> {
+        self.get_power_mode(zone)?;
+        self.set_power(zone)?;
+        if self.fan_rpm[Self::zone_index(zone)] != 0 {
+            self.set_rpm(zone)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Sets every fan to the same speed, 0 means auto
+    pub fn set_fan_rpm(&mut self, value: u16) -> Result<(), DeviceError> {
         if self.power != 4 {
-            match value == 0 {
-                true => self.fan_rpm = value as u8,
-                false => self.fan_rpm = self.clamp_fan(value),
-            }
-            self.get_power_mode(0x01);
-            self.set_power(0x01);
-            if value != 0 {
-                self.set_rpm(0x01);
-            }
-            self.get_power_mode(0x02);
-            self.set_power(0x02);
-            if value != 0 {
-                self.set_rpm(0x02);
+            for zone in [0x01, 0x02] {
+                let index = Self::zone_index(zone);
+                match value == 0 {
+                    true => self.fan_rpm[index] = value as u8,
+                    false => self.fan_rpm[index] = self.clamp_fan(index, value),
+                }
+                self.apply_fan(zone)?;
             }
         }
 
-        return true;
+        return Ok(());
     }
 
-    pub fn get_fan_rpm(&mut self) -> u16 {
-        let res: u16 = self.fan_rpm as u16;
-        return res * 100;
-    }
+    /// Sets the speed of one fan zone (0 for the CPU, 1 for the GPU)
+    pub fn set_zone_fan_rpm(&mut self, zone: usize, value: u16) -> Result<(), DeviceError> {
+        if zone >= self.fan_zone_count() {
+            return Err(DeviceError::NotSupported);
+        }
+        if self.power != 4 {
+            match value == 0 {
+                true => self.fan_rpm[zone] = 0,
+                false => self.fan_rpm[zone] = self.clamp_fan(zone, value),
+            }
+            self.apply_fan(zone as u8 + 1)?;
+        }
 
+        return Ok(());
+    }
 
+    /// Asks the EC for the fan speed, 0 means the fans are in auto mode
+    pub fn get_fan_rpm(&mut self) -> Result<u16, DeviceError> {
+        return self.get_zone_fan_rpm(0);
+    }
 
+    /// Asks the EC for the speed of one fan zone (0 for the CPU, 1 for the GPU)
+    pub fn get_zone_fan_rpm(&mut self, zone: usize) -> Result<u16, DeviceError> {
+        let zone = zone as u8 + 1;
+        match self.execute(EcCommand::GetPowerMode { zone })? {
+            EcResponse::PowerMode { manual_fan: false, .. } => return Ok(0),
+            EcResponse::PowerMode { .. } => {},
+            _ => return Err(DeviceError::ResponseMismatch),
+        }
 
+        match self.execute(EcCommand::GetFanRpm { zone })? {
+            EcResponse::FanRpm { rpm, .. } => Ok(rpm),
+            _ => Err(DeviceError::ResponseMismatch),
+        }
+    }
 
-    pub fn get_bho(&mut self) -> Option<u8> {
+    pub fn get_bho(&mut self) -> Result<(bool, u8), DeviceError> {
         if !self.have_feature("bho".to_string()) {
-            return None;
+            return Err(DeviceError::NotSupported);
         }
 
-        let mut report: RazerPacket = RazerPacket::new(0x07, 0x92, 0x01);
-        report.args[0] = 0x00;
-
-        return self.send_report(report)
-            .map(|resp| resp.args[0]);
+        match self.execute(EcCommand::GetBho)? {
+            EcResponse::Bho { is_on, threshold } => Ok((is_on, threshold)),
+            _ => Err(DeviceError::ResponseMismatch),
+        }
     }
 
-    pub fn set_bho(&mut self, is_on: bool, threshold: u8) -> bool {
+    /// The model has the optimizer and takes this threshold
+    pub fn check_bho(&mut self, threshold: u8) -> Result<(), DeviceError> {
         if !self.have_feature("bho".to_string()) {
-            return false;
+            return Err(DeviceError::NotSupported);
         }
+        let limits = self.limits();
+        if !limits.valid_bho_threshold(threshold) {
+            let (min, max) = limits.bho_threshold;
+            return Err(DeviceError::InvalidArgument(format!("BHO threshold must be a multiple of {} between {} and {}, got {}", limits.bho_step, min, max, threshold)));
+        }
+        return Ok(());
+    }
 
-        let mut report = RazerPacket::new(0x07, 0x12, 0x01);
-        report.args[0] = bho_to_byte(is_on, threshold);
+    pub fn set_bho(&mut self, is_on: bool, threshold: u8) -> Result<(), DeviceError> {
+        self.check_bho(threshold)?;
+
+        // The threshold only matters while the optimizer is on
+        return self.set_verified(EcCommand::SetBho { is_on, threshold }, EcCommand::GetBho,
+            |r| matches!(r, EcResponse::Bho { is_on: on, threshold: th } if *on == is_on && (!is_on || *th == threshold)));
+    }
 
-        return self.send_report(report)
-            .map_or(false, |r| { 
-                println!("Response Packet:\n{:#?}", r); 
-                true
-            } 
-        );
+    fn record_exchange(&mut self, attempt: u8, request: &[u8], response: Option<&[u8]>, error: Option<String>) {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record(attempt, request, response, error);
+        }
     }
 
-    fn send_report(&mut self, mut report: RazerPacket) -> Option<RazerPacket>{
-        let mut temp_buf: [u8; 91] = [0x00; 91];
-        for _ in 0..3 {
-            match self.device.send_feature_report(report.calc_crc().as_slice()) {
+    fn send_report(&mut self, mut report: RazerPacket) -> Result<RazerPacket, DeviceError> {
+        let mut temp_buf: [u8; packet::REPORT_SIZE] = [0x00; packet::REPORT_SIZE];
+        let mut error = DeviceError::Busy;
+        for attempt in 0..3 {
+            let request = if self.send_crc {
+                report.calc_crc()
+            } else {
+                bincode::serialize(&report).unwrap()
+            };
+            match self.device.send_feature_report(request.as_slice()) {
                 Ok(_) => {
                     thread::sleep(time::Duration::from_micros(1000));
                     match self.device.get_feature_report(&mut temp_buf) {
                         Ok(size) => {
-                            if size == 91 {
+                            self.record_exchange(attempt, &request, Some(&temp_buf[..size]), None);
+                            if size == packet::REPORT_SIZE {
                                 match bincode::deserialize::<RazerPacket>(&temp_buf){
                                     Ok(response) => {
                                         // when request bho status the response command id is different from the request command id...
-                                        if response.command_id == 0x92 {
-                                            return Some(response);
-                                        }
+                                        let bho_status = response.command_id == 0x92;
 
-                                        if response.remaining_packets != report.remaining_packets || 
+                                        if !bho_status && (response.remaining_packets != report.remaining_packets || 
                                             response.command_class != report.command_class ||
-                                                response.command_id != report.command_id {
+                                                response.command_id != report.command_id) {
                                                     eprintln!("Response doesn't match request");
+                                                    error = DeviceError::ResponseMismatch;
                                                 }
-                                        else if response.status == RazerPacket::RAZER_CMD_SUCCESSFUL {
-                                            return Some(response);
-                                        }
-                                        if response.status == RazerPacket::RAZER_CMD_NOT_SUPPORTED {
-                                            eprintln!("Command not supported");
+                                        else {
+                                            match response.status {
+                                                RazerPacket::RAZER_CMD_SUCCESSFUL => return Ok(response),
+                                                RazerPacket::RAZER_CMD_NOT_SUPPORTED => {
+                                                    // Retrying won't change the answer
+                                                    eprintln!("Command not supported");
+                                                    error = DeviceError::NotSupported;
+                                                    break;
+                                                },
+                                                RazerPacket::RAZER_CMD_FAILURE => error = DeviceError::Failure,
+                                                RazerPacket::RAZER_CMD_TIMEOUT => error = DeviceError::Timeout,
+                                                _ => error = DeviceError::Busy,
+                                            }
                                         }
                                     },
                                     Err(e) => {
                                         eprintln!("Error: {}", e);
+                                        error = DeviceError::ResponseMismatch;
                                     }
                                 }
                             } else {
                                 eprintln!("Invalid report length: {:?}", size);
+                                error = DeviceError::ResponseMismatch;
                             }
                         },
                         Err(e) => {
+                            self.record_exchange(attempt, &request, None, Some(e.to_string()));
                             eprintln!("Error: {}", e);
+                            error = DeviceError::Transport(e.to_string());
                         }
                     }
                 },
                 Err(e) => {
+                    self.record_exchange(attempt, &request, None, Some(e.to_string()));
                     eprintln!("Error: {}", e);
+                    error = DeviceError::Transport(e.to_string());
                 }
             };
 
         }
 
         thread::sleep(time::Duration::from_micros(8000));
-        return None;
+        return Err(error);
     }
 
 }
 
-// top bit flags whether battery health optimization is on or off
-// bottom bits are the actual threshold that it is set to
-fn byte_to_bho(u: u8) -> (bool, u8) {
-    return (u & (1 << 7) != 0, (u & 0b0111_1111));
+pub fn check_ac(ac: usize) -> Result<(), DeviceError> {
+    if ac > 1 {
+        return Err(DeviceError::InvalidArgument(format!("AC state must be 0 (battery) or 1 (AC), got {}", ac)));
+    }
+    return Ok(());
 }
 
-fn bho_to_byte(is_on: bool, threshold: u8) -> u8 {
-    if is_on {
-        return threshold | 0b1000_0000;
+fn check_fan_rpm(rpm: i32) -> Result<(), DeviceError> {
+    if rpm < 0 || rpm > u16::MAX as i32 {
+        return Err(DeviceError::InvalidArgument(format!("invalid fan speed {}", rpm)));
+    }
+    return Ok(());
+}
+
+/// Power mode and, for custom mode, the boosts are within the model limits
+fn check_power_mode(limits: &Limits, mode: u8, cpu_boost: u8, gpu_boost: u8) -> Result<(), DeviceError> {
+    if !limits.has_power_mode(mode) {
+        return Err(DeviceError::InvalidArgument(format!("power mode {} is not available, use one of {:?}", mode, limits.power_modes)));
+    }
+    if mode == 4 && cpu_boost > limits.max_cpu_boost {
+        return Err(DeviceError::InvalidArgument(format!("CPU boost must be between 0 and {}, got {}", limits.max_cpu_boost, cpu_boost)));
+    }
+    if mode == 4 && gpu_boost > limits.max_gpu_boost {
+        return Err(DeviceError::InvalidArgument(format!("GPU boost must be between 0 and {}, got {}", limits.max_gpu_boost, gpu_boost)));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+    use service::Capabilities;
+
+    fn laptop() -> RazerLaptop<MemoryTransport> {
+        let info = SupportedDevice {
+            name: "Blade 15".to_string(),
+            vid: "1532".to_string(),
+            pid: "0000".to_string(),
+            features: vec!["boost".to_string(), "bho".to_string()],
+            fan: vec![3500, 5000],
+            fan_zones: vec![],
+            interface: None,
+            capabilities: Capabilities::default(),
+            dmi: None,
+        };
+        return RazerLaptop::new(&info, MemoryTransport::new());
+    }
+
+    /// Queues the EC answers of `exchanges` and returns the reports the
+    /// daemon should send for them
+    fn expect(laptop: &mut RazerLaptop<MemoryTransport>, exchanges: &[(EcCommand, EcResponse)]) -> Vec<Vec<u8>> {
+        let mut sent = vec![];
+        for (command, response) in exchanges {
+            let mut request = command.encode().unwrap();
+            if laptop.send_crc {
+                sent.push(request.calc_crc());
+            } else {
+                sent.push(bincode::serialize(&request).unwrap());
+            }
+
+            let mut answer = command.encode().unwrap();
+            command.encode_response(response, &mut answer);
+            answer.status = RazerPacket::RAZER_CMD_SUCCESSFUL;
+            laptop.device.push_response(answer.calc_crc());
+        }
+        return sent;
+    }
+
+    fn power_mode(zone: u8, mode: u8, manual_fan: bool) -> EcResponse {
+        return EcResponse::PowerMode { zone, mode, manual_fan };
+    }
+
+    #[test]
+    fn set_power_mode_sets_both_zones() {
+        let mut laptop = laptop();
+        let expected = expect(&mut laptop, &[
+            (EcCommand::SetPowerMode { zone: 0x01, mode: 1, manual_fan: false }, EcResponse::Done),
+            (EcCommand::GetPowerMode { zone: 0x01 }, power_mode(0x01, 1, false)),
+            (EcCommand::SetPowerMode { zone: 0x02, mode: 1, manual_fan: false }, EcResponse::Done),
+            (EcCommand::GetPowerMode { zone: 0x02 }, power_mode(0x02, 1, false)),
+        ]);
+
+        assert!(laptop.set_power_mode(1, 0, 0).is_ok());
+        assert_eq!(laptop.device.sent, expected);
+    }
+
+    #[test]
+    fn crc_is_only_sent_when_asked_for() {
+        for send_crc in [false, true] {
+            let mut laptop = laptop();
+            laptop.send_crc = send_crc;
+            let expected = expect(&mut laptop, &[(EcCommand::GetBho, EcResponse::Bho { is_on: true, threshold: 80 })]);
+
+            assert!(laptop.get_bho().is_ok());
+            assert_eq!(laptop.device.sent, expected);
+            let report: RazerPacket = bincode::deserialize(&laptop.device.sent[0]).unwrap();
+            assert_eq!(report.crc != 0, send_crc);
+        }
+    }
+
+    #[test]
+    fn set_power_mode_custom_sets_the_boosts() {
+        let mut laptop = laptop();
+        let expected = expect(&mut laptop, &[
+            (EcCommand::GetPowerMode { zone: 0x01 }, power_mode(0x01, 0, false)),
+            (EcCommand::SetPowerMode { zone: 0x01, mode: 4, manual_fan: false }, EcResponse::Done),
+            (EcCommand::GetPowerMode { zone: 0x01 }, power_mode(0x01, 4, false)),
+            (EcCommand::GetBoost { target: BoostTarget::Cpu }, EcResponse::Boost { target: BoostTarget::Cpu, level: 1 }),
+            (EcCommand::SetBoost { target: BoostTarget::Cpu, level: 3 }, EcResponse::Done),
+            (EcCommand::GetBoost { target: BoostTarget::Cpu }, EcResponse::Boost { target: BoostTarget::Cpu, level: 3 }),
+            (EcCommand::GetBoost { target: BoostTarget::Gpu }, EcResponse::Boost { target: BoostTarget::Gpu, level: 0 }),
+            (EcCommand::SetBoost { target: BoostTarget::Gpu, level: 2 }, EcResponse::Done),
+            (EcCommand::GetBoost { target: BoostTarget::Gpu }, EcResponse::Boost { target: BoostTarget::Gpu, level: 2 }),
+            (EcCommand::GetPowerMode { zone: 0x02 }, power_mode(0x02, 0, false)),
+            (EcCommand::SetPowerMode { zone: 0x02, mode: 4, manual_fan: false }, EcResponse::Done),
+            (EcCommand::GetPowerMode { zone: 0x02 }, power_mode(0x02, 4, false)),
+        ]);
+
+        assert!(laptop.set_power_mode(4, 3, 2).is_ok());
+        assert_eq!(laptop.device.sent, expected);
+    }
+
+    #[test]
+    fn set_fan_rpm_sets_manual_mode_then_the_speed() {
+        let mut laptop = laptop();
+        let mut exchanges = vec![];
+        for zone in [0x01, 0x02] {
+            exchanges.extend([
+                (EcCommand::GetPowerMode { zone }, power_mode(zone, 0, false)),
+                (EcCommand::SetPowerMode { zone, mode: 0, manual_fan: true }, EcResponse::Done),
+                (EcCommand::GetPowerMode { zone }, power_mode(zone, 0, true)),
+                (EcCommand::SetFanRpm { zone, rpm: 4200 }, EcResponse::Done),
+                (EcCommand::GetFanRpm { zone }, EcResponse::FanRpm { zone, rpm: 4200 }),
+            ]);
+        }
+        let expected = expect(&mut laptop, &exchanges);
+
+        // Truncated to hundreds on the wire
+        assert!(laptop.set_fan_rpm(4250).is_ok());
+        assert_eq!(laptop.device.sent, expected);
+    }
+
+    #[test]
+    fn set_bho_reads_the_setting_back() {
+        let mut laptop = laptop();
+        let expected = expect(&mut laptop, &[
+            (EcCommand::SetBho { is_on: true, threshold: 80 }, EcResponse::Done),
+            (EcCommand::GetBho, EcResponse::Bho { is_on: true, threshold: 80 }),
+        ]);
+
+        assert!(laptop.set_bho(true, 80).is_ok());
+        assert_eq!(laptop.device.sent, expected);
+    }
+
+    #[test]
+    fn set_bho_is_sent_again_when_not_applied() {
+        let mut laptop = laptop();
+        let expected = expect(&mut laptop, &[
+            (EcCommand::SetBho { is_on: true, threshold: 80 }, EcResponse::Done),
+            (EcCommand::GetBho, EcResponse::Bho { is_on: false, threshold: 80 }),
+            (EcCommand::SetBho { is_on: true, threshold: 80 }, EcResponse::Done),
+            (EcCommand::GetBho, EcResponse::Bho { is_on: true, threshold: 80 }),
+        ]);
+
+        assert!(laptop.set_bho(true, 80).is_ok());
+        assert_eq!(laptop.device.sent, expected);
     }
-    return threshold;
 }
@@ -0,0 +1,32 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+use crate::comms::DaemonEvent;
+
+/// Hands the daemon events to the subscribed clients.
+///
+/// Every subscriber gets its own channel, those whose receiver is gone are
+/// dropped on the next event.
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<DaemonEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        return EventBus { subscribers: Mutex::new(vec![]) };
+    }
+
+    pub fn subscribe(&self) -> Receiver<DaemonEvent> {
+        let (sender, events) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        return events;
+    }
+
+    pub fn publish(&self, event: DaemonEvent) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+}
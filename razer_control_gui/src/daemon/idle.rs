@@ -0,0 +1,76 @@
+use crate::dbus_mutter_idlemonitor;
+
+/// Mutter idle/active watches used to turn the lights off when idle.
+///
+/// Kept out of the `DeviceManager` so the D-Bus threads never have to wait
+/// for the device thread.
+pub struct IdleWatch {
+    pub idle_id: u32,
+    pub active_id: u32,
+    add_active: bool,
+    change_idle: bool,
+    timeout: u64, // miliseconds, 0 means no idle watch
+}
+
+impl IdleWatch {
+    pub fn new() -> IdleWatch {
+        return IdleWatch {
+            idle_id: 0,
+            active_id: 0,
+            add_active: false,
+            change_idle: false,
+            timeout: 0,
+        };
+    }
+
+    /// Idle timeout of the current AC state, the watch is replaced on the
+    /// next `add_idle_watch`
+    pub fn set_timeout(&mut self, idle: u32) {
+        self.timeout = idle as u64 * 60 * 1000; // idle is in minutes timeout is in miliseconds
+        self.change_idle = true;
+    }
+
+    pub fn add_idle_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
+        if self.change_idle {
+            if self.timeout != 0 {
+                if self.idle_id != 0 {
+                    self.remove_watch(proxy_idle);
+                }
+                if let Ok(id) = proxy_idle.add_idle_watch(self.timeout) {
+                    println!("idle handler {:?}", id);
+                    self.idle_id = id;
+                }
+            } else {
+                if self.idle_id != 0 {
+                    self.remove_watch(proxy_idle);
+                }
+            }
+            self.change_idle = false;
+        }
+    }
+
+    fn remove_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
+        if let Ok(_) = proxy_idle.remove_watch(self.idle_id) {
+            println!("remove idle handler");
+        }
+    }
+
+    pub fn add_active_watch(&mut self, proxy_idle: &dyn dbus_mutter_idlemonitor::OrgGnomeMutterIdleMonitor) {
+        if self.add_active {
+            if let Ok(id) = proxy_idle.add_user_active_watch() {
+                println!("active handler {:?}", id);
+                self.active_id = id;
+            }
+        }
+    }
+
+    pub fn light_off(&mut self) {
+        if self.idle_id != 0 {
+            self.add_active = true;
+        }
+    }
+
+    pub fn restore_light(&mut self) {
+        self.add_active = false;
+    }
+}
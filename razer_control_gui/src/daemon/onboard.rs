@@ -0,0 +1,229 @@
+use std::fmt::Write as _;
+use std::fs;
+
+use hidapi::HidApi;
+use service::{Capabilities, DeviceDatabase, DmiInfo, SupportedDevice};
+
+use crate::command::{BoostTarget, EcCommand};
+use crate::device::{self, RazerLaptop};
+use crate::transport::{DynTransport, SocketTransport};
+
+/// Everything sent to a candidate interface. Only queries, nothing here may
+/// change a setting of a model we know nothing about. The serial number is
+/// left out, the report is meant to be attached to a public issue.
+const QUERIES: [EcCommand; 8] = [
+    EcCommand::GetPowerMode { zone: 0x01 },
+    EcCommand::GetPowerMode { zone: 0x02 },
+    EcCommand::GetFanRpm { zone: 0x01 },
+    EcCommand::GetFanRpm { zone: 0x02 },
+    EcCommand::GetBoost { target: BoostTarget::Cpu },
+    EcCommand::GetBoost { target: BoostTarget::Gpu },
+    EcCommand::GetBho,
+    EcCommand::GetFirmwareVersion,
+];
+
+/// A Razer model that is not in the device database
+struct UnknownModel {
+    vid: u16,
+    pid: u16,
+    product: String,
+    /// Description, interface number and transport of every candidate
+    interfaces: Vec<(String, Option<i32>, Result<DynTransport, String>)>,
+}
+
+/// Probes the Razer devices missing from the device database and writes a
+/// proposed laptops.json entry and a report for each one to `out_dir`.
+/// Returns whether an EC answered on any of them.
+pub fn run(out_dir: &str) -> bool {
+    let database = DeviceDatabase::load();
+    let known: Vec<(u16, u16)> = database.devices.iter().filter_map(|d| d.ids()).collect();
+
+    let models = match std::env::var(device::SIM_SOCKET_ENV) {
+        Ok(path) => find_simulated(&path).into_iter().filter(|m| !known.contains(&(m.vid, m.pid))).collect(),
+        Err(_) => find_unknown(&known),
+    };
+    if models.is_empty() {
+        println!("No unknown Razer device found, nothing to onboard");
+        return false;
+    }
+
+    let fan = conservative_fan_range(&database.devices);
+    let mut found = false;
+    for model in models {
+        found |= onboard(model, fan, out_dir);
+    }
+    return found;
+}
+
+fn find_simulated(path: &str) -> Vec<UnknownModel> {
+    match SocketTransport::connect(path) {
+        Ok(transport) => {
+            let (vid, pid) = (transport.vid, transport.pid);
+            return vec![UnknownModel {
+                vid,
+                pid,
+                product: String::new(),
+                interfaces: vec![(format!("simulator at {}", path), None, Ok(Box::new(transport)))],
+            }];
+        },
+        Err(e) => {
+            eprintln!("Error connecting to simulator at {}: {}", path, e);
+            return vec![];
+        }
+    }
+}
+
+fn find_unknown(known: &[(u16, u16)]) -> Vec<UnknownModel> {
+    let api = match HidApi::new() {
+        Ok(api) => api,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return vec![];
+        }
+    };
+
+    let mut models: Vec<UnknownModel> = vec![];
+    // hidapi lists every top level collection, they share the hidraw node
+    let mut tried: Vec<&std::ffi::CStr> = vec![];
+    for info in api.device_list().filter(|d| d.vendor_id() == device::RAZER_VENDOR_ID) {
+        let ids = (info.vendor_id(), info.product_id());
+        if known.contains(&ids) || tried.contains(&info.path()) {
+            continue;
+        }
+        tried.push(info.path());
+
+        let description = format!(
+            "interface {}, usage page {:04x}, usage {:04x}, {}",
+            info.interface_number(), info.usage_page(), info.usage(), info.path().to_string_lossy()
+        );
+        let transport = api.open_path(info.path())
+            .map(|dev| -> DynTransport { Box::new(dev) })
+            .map_err(|e| e.to_string());
+        let candidate = (description, Some(info.interface_number()), transport);
+
+        match models.iter_mut().find(|m| (m.vid, m.pid) == ids) {
+            Some(model) => model.interfaces.push(candidate),
+            None => models.push(UnknownModel {
+                vid: ids.0,
+                pid: ids.1,
+                product: info.product_string().unwrap_or_default().to_string(),
+                interfaces: vec![candidate],
+            }),
+        }
+    }
+    return models;
+}
+
+/// A fan range every known model accepts
+fn conservative_fan_range(devices: &[SupportedDevice]) -> (u16, u16) {
+    let min = devices.iter().filter_map(|d| d.fan.first()).max().copied().unwrap_or(3500);
+    let max = devices.iter().filter_map(|d| d.fan.get(1)).min().copied().unwrap_or(4300);
+    if min >= max {
+        return (3500, 4300);
+    }
+    return (min, max);
+}
+
+/// Name of the laptop from the firmware, falling back to the USB product name
+fn model_name(model: &UnknownModel) -> String {
+    let dmi = DmiInfo::from_env();
+    if !dmi.product_name.is_empty() {
+        return dmi.product_name;
+    }
+    if !model.product.is_empty() {
+        return model.product.clone();
+    }
+    return format!("Razer laptop {:04x}", model.pid);
+}
+
+fn onboard(model: UnknownModel, fan: (u16, u16), out_dir: &str) -> bool {
+    let mut entry = SupportedDevice {
+        name: model_name(&model),
+        vid: format!("{:04x}", model.vid),
+        pid: format!("{:04x}", model.pid),
+        features: vec![],
+        fan: vec![fan.0, fan.1],
+        fan_zones: vec![],
+        interface: None,
+        capabilities: Capabilities::default(),
+        dmi: None,
+    };
+
+    let mut report = String::new();
+    let _ = writeln!(report, "razer-laptop-control {} onboarding report", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "Device: {:04x}:{:04x} {:?}", model.vid, model.pid, model.product);
+    let _ = writeln!(report, "Model: {}", entry.name);
+    let _ = writeln!(report, "DMI: {:?}", DmiInfo::from_env());
+    if let Ok(kernel) = fs::read_to_string("/proc/sys/kernel/osrelease") {
+        let _ = writeln!(report, "Kernel: {}", kernel.trim());
+    }
+    println!("Onboarding {:04x}:{:04x} ({}), candidate interfaces:", model.vid, model.pid, entry.name);
+
+    let mut ec_found = false;
+    let mut notes: Vec<String> = vec![];
+    for (description, number, transport) in model.interfaces {
+        println!("  {}", description);
+        let _ = writeln!(report, "\nInterface: {}", description);
+        let transport = match transport {
+            Ok(transport) => transport,
+            Err(e) => {
+                let _ = writeln!(report, "  could not open: {}", e);
+                continue;
+            }
+        };
+
+        let mut laptop = RazerLaptop::new(&entry, transport);
+        let answers: Vec<_> = QUERIES.iter().map(|query| (*query, laptop.query(*query))).collect();
+        for (query, answer) in &answers {
+            let _ = writeln!(report, "  {:?}: {:?}", query, answer);
+        }
+        // Same test as the daemon uses to pick an interface
+        if ec_found || answers[0].1.is_err() {
+            continue;
+        }
+        ec_found = true;
+        entry.interface = number;
+        println!("    the EC answers here");
+
+        for (feature, command, conclusive) in device::FEATURE_PROBES {
+            match answers.iter().find(|(query, _)| *query == command).map(|(_, answer)| answer) {
+                Some(Ok(_)) if conclusive => entry.features.push(feature.to_string()),
+                Some(Ok(_)) => notes.push(format!("{}: the EC answers the query, check that the setting works before adding it", feature)),
+                _ => {},
+            }
+        }
+    }
+    notes.push("logo, creator_mode: cannot be detected without changing them, add them if the laptop has them".to_string());
+    notes.push(format!("fan: {}-{} RPM is a range every known model accepts, the real one is likely wider", fan.0, fan.1));
+
+    let _ = writeln!(report, "\nNotes:");
+    for note in &notes {
+        let _ = writeln!(report, "  {}", note);
+    }
+
+    let report_path = format!("{}/onboard-{:04x}.txt", out_dir, model.pid);
+    if !ec_found {
+        let _ = writeln!(report, "\nNo interface answered the EC queries, no entry proposed");
+        write_file(&report_path, &report);
+        println!("No interface answered, see {}", report_path);
+        return false;
+    }
+
+    let json = serde_json::to_string_pretty(&vec![&entry]).unwrap_or_default();
+    let _ = writeln!(report, "\nProposed entry:\n{}", json);
+    let entry_path = format!("{}/laptop-{:04x}.json", out_dir, model.pid);
+    write_file(&entry_path, &json);
+    write_file(&report_path, &report);
+
+    println!("Proposed entry: {}", entry_path);
+    println!("Report: {}", report_path);
+    println!("Review the entry and copy it to {}/ to use it,", service::get_device_dir_path());
+    println!("then attach the report to a \"new model\" issue");
+    return true;
+}
+
+fn write_file(path: &str, contents: &str) {
+    if let Err(e) = fs::write(path, contents) {
+        eprintln!("Error writing {}: {}", path, e);
+    }
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+
+/// Size of a serialized `RazerPacket`, this is also the feature report size
+pub const REPORT_SIZE: usize = 91;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RazerPacket {
+    pub report: u8,
+    pub status: u8,
+    pub id: u8,
+    pub remaining_packets: u16,
+    pub protocol_type: u8,
+    pub data_size: u8,
+    pub command_class: u8,
+    pub command_id: u8,
+    #[serde(with = "BigArray")]
+    pub args: [u8; 80],
+    pub crc: u8,
+    pub reserved: u8,
+}
+
+#[allow(dead_code)]
+impl RazerPacket {
+// Command status
+    pub const RAZER_CMD_NEW:u8 = 0x00;
+    pub const RAZER_CMD_BUSY:u8 = 0x01;
+    pub const RAZER_CMD_SUCCESSFUL:u8 = 0x02;
+    pub const RAZER_CMD_FAILURE:u8 = 0x03;
+    pub const RAZER_CMD_TIMEOUT:u8 =0x04;
+    pub const RAZER_CMD_NOT_SUPPORTED:u8 = 0x05;
+
+    pub fn new(command_class: u8, command_id: u8, data_size: u8) -> RazerPacket {
+        return RazerPacket {
+            report: 0x00,
+            status: RazerPacket::RAZER_CMD_NEW,
+            id: 0x1F,
+            remaining_packets: 0x0000,
+            protocol_type: 0x00,
+            data_size,
+            command_class,
+            command_id,
+            args: [0x00; 80],
+            crc: 0x00,
+            reserved: 0x00,
+        };
+    }
+
+    /// Sets the CRC and returns the report to send
+    pub fn calc_crc(&mut self) -> Vec<u8>{
+        let mut res: u8 = 0x00;
+        let buf: Vec<u8> = bincode::serialize(self).unwrap();
+        for i in 2..88 {
+            res ^= buf[i];
+        }
+
+        // The CRC is not part of the checksummed bytes, serialize again to
+        // get it into the report
+        self.crc = res;
+        return bincode::serialize(self).unwrap();
+    }
+}
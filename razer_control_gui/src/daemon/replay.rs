@@ -0,0 +1,112 @@
+use crate::capture::{self, Capture, CaptureRecord};
+use crate::command::{EcCommand, EcResponse};
+use crate::comms::DeviceError;
+use crate::device::{self, DeviceManager};
+use crate::packet::RazerPacket;
+
+/// Sends the commands of a capture through `RazerLaptop` to the simulator
+/// named by `SIM_SOCKET_ENV` and compares the outcome of each exchange with
+/// the one recorded from the real device.
+///
+/// Retries are up to the daemon, so only the first attempt of an exchange is
+/// replayed. Returns the number of exchanges where the outcomes differ, None
+/// when the replay could not run.
+pub fn run(path: &str) -> Option<usize> {
+    let records = match Capture::read(path) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Error reading capture {}: {}", path, e);
+            return None;
+        }
+    };
+    if std::env::var(device::SIM_SOCKET_ENV).is_err() {
+        eprintln!("Set {} to the socket of a razer-sim to replay against", device::SIM_SOCKET_ENV);
+        return None;
+    }
+    // Don't append the replay to a capture
+    std::env::remove_var(capture::CAPTURE_FILE_ENV);
+
+    let mut manager = match DeviceManager::read_laptops_file() {
+        Ok(manager) => manager,
+        Err(_) => DeviceManager::new(),
+    };
+    manager.discover_devices();
+    let laptop = match manager.device.as_mut() {
+        Some(laptop) => laptop,
+        None => {
+            eprintln!("The simulator emulates no supported device");
+            return None;
+        }
+    };
+
+    let exchanges = exchanges(&records);
+    let mut divergences = 0;
+    for (i, attempts) in exchanges.iter().enumerate() {
+        let first = &attempts[0];
+        let command = match capture::raw_bytes(&first.request)
+            .and_then(|bytes| bincode::deserialize::<RazerPacket>(&bytes).ok())
+            .and_then(|request| EcCommand::decode(&request)) {
+            Some(command) => command,
+            None => {
+                println!("#{} {:02x}:{:02x} unknown command, skipped", i, first.request.command_class, first.request.command_id);
+                continue;
+            }
+        };
+
+        if command == EcCommand::GetSerial {
+            println!("#{} {:?} is blanked out in captures, skipped", i, command);
+            continue;
+        }
+
+        let recorded = recorded_outcome(command, attempts);
+        if let Err(DeviceError::Transport(e)) = &recorded {
+            // The EC may or may not have seen it, we can't tell
+            println!("#{} {:?} had no response ({}), skipped", i, command, e);
+            continue;
+        }
+
+        let replayed = laptop.execute(command);
+        if replayed != recorded {
+            divergences += 1;
+            println!("#{} {:?} ({} attempts): device {:?}, simulator {:?}",
+                i, command, attempts.len(), recorded, replayed);
+        }
+    }
+
+    println!("Replayed {} exchanges, {} divergences", exchanges.len(), divergences);
+    return Some(divergences);
+}
+
+/// Groups the records by `send_report` call, a call starts with attempt 0
+fn exchanges(records: &[CaptureRecord]) -> Vec<&[CaptureRecord]> {
+    let mut exchanges = vec![];
+    let mut start = 0;
+    for i in 1..=records.len() {
+        if i == records.len() || records[i].attempt == 0 {
+            exchanges.push(&records[start..i]);
+            start = i;
+        }
+    }
+    return exchanges;
+}
+
+/// What `execute` returned on the real device, from its last attempt
+fn recorded_outcome(command: EcCommand, attempts: &[CaptureRecord]) -> Result<EcResponse, DeviceError> {
+    let last = &attempts[attempts.len() - 1];
+    let response = match &last.response {
+        Some(response) => response,
+        None => return Err(DeviceError::Transport(last.error.clone().unwrap_or_default())),
+    };
+    let packet = match capture::raw_bytes(response)
+        .and_then(|bytes| bincode::deserialize::<RazerPacket>(&bytes).ok()) {
+        Some(packet) => packet,
+        None => return Err(DeviceError::ResponseMismatch),
+    };
+    match packet.status {
+        RazerPacket::RAZER_CMD_SUCCESSFUL => Ok(command.decode_response(&packet)),
+        RazerPacket::RAZER_CMD_NOT_SUPPORTED => Err(DeviceError::NotSupported),
+        RazerPacket::RAZER_CMD_FAILURE => Err(DeviceError::Failure),
+        RazerPacket::RAZER_CMD_TIMEOUT => Err(DeviceError::Timeout),
+        _ => Err(DeviceError::Busy),
+    }
+}
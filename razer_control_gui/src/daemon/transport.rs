@@ -0,0 +1,124 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+
+/// Something that can carry 91 byte feature reports to and from the EC.
+///
+/// `RazerLaptop` only talks to the embedded controller through this trait, so
+/// the power/fan/BHO logic can run against something other than a hidraw node.
+pub trait Transport {
+    fn send_feature_report(&mut self, data: &[u8]) -> io::Result<()>;
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+impl Transport for hidapi::HidDevice {
+    fn send_feature_report(&mut self, data: &[u8]) -> io::Result<()> {
+        hidapi::HidDevice::send_feature_report(self, data)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        hidapi::HidDevice::get_feature_report(self, buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+/// Transport picked at runtime, either a hidraw node or the simulator
+pub type DynTransport = Box<dyn Transport + Send>;
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_feature_report(&mut self, data: &[u8]) -> io::Result<()> {
+        (**self).send_feature_report(data)
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).get_feature_report(buf)
+    }
+}
+
+/// Talks to a simulated EC (`razer-sim`) over a UNIX socket.
+///
+/// Right after connecting the simulator sends the VID and PID it emulates
+/// (little endian u16 each). After that every feature report is written and
+/// read as a raw `REPORT_SIZE` byte block.
+pub struct SocketTransport {
+    stream: UnixStream,
+    pub vid: u16,
+    pub pid: u16,
+}
+
+impl SocketTransport {
+    pub fn connect(path: &str) -> io::Result<SocketTransport> {
+        let mut stream = UnixStream::connect(path)?;
+        let mut ids = [0u8; 4];
+        stream.read_exact(&mut ids)?;
+        Ok(SocketTransport {
+            stream,
+            vid: u16::from_le_bytes([ids[0], ids[1]]),
+            pid: u16::from_le_bytes([ids[2], ids[3]]),
+        })
+    }
+}
+
+impl Transport for SocketTransport {
+    fn send_feature_report(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data)
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read_exact(buf)?;
+        Ok(buf.len())
+    }
+}
+
+/// In-memory transport, keeps every report sent to it.
+///
+/// Queued responses are handed out first; once the queue is empty the last
+/// request is echoed back with a successful status, which is what the EC does
+/// for every command we send.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryTransport {
+    pub sent: Vec<Vec<u8>>,
+    responses: std::collections::VecDeque<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl MemoryTransport {
+    const STATUS_OFFSET: usize = 1;
+    const STATUS_SUCCESSFUL: u8 = 0x02;
+
+    pub fn new() -> MemoryTransport {
+        return MemoryTransport::default();
+    }
+
+    /// Queues a raw response for the next `get_feature_report`
+    pub fn push_response(&mut self, response: Vec<u8>) {
+        self.responses.push_back(response);
+    }
+}
+
+#[cfg(test)]
+impl Transport for MemoryTransport {
+    fn send_feature_report(&mut self, data: &[u8]) -> io::Result<()> {
+        self.sent.push(data.to_vec());
+        Ok(())
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let response = match self.responses.pop_front() {
+            Some(response) => response,
+            None => {
+                let mut echo = self.sent.last()
+                    .cloned()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::WouldBlock, "no report sent"))?;
+                if echo.len() > MemoryTransport::STATUS_OFFSET {
+                    echo[MemoryTransport::STATUS_OFFSET] = MemoryTransport::STATUS_SUCCESSFUL;
+                }
+                echo
+            }
+        };
+        let size = response.len().min(buf.len());
+        buf[..size].copy_from_slice(&response[..size]);
+        Ok(size)
+    }
+}
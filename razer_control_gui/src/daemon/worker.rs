@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use log::*;
+
+use crate::comms;
+use crate::device::{self, DeviceManager};
+use crate::events::EventBus;
+use crate::idle::IdleWatch;
+
+/// Work for the device thread
+pub enum Job {
+    /// A client request, the response goes back through the sender
+    Client(comms::DaemonCommand, Sender<comms::DaemonResponse>),
+    /// The AC adapter was plugged in or out
+    SetAcState(bool),
+    /// Ask UPower for the AC state again, e.g. after a resume
+    RefreshAcState,
+    /// The screen got blanked or came back
+    Screensaver(bool),
+    /// A Razer hidraw node was added
+    DeviceAdded,
+    /// A hidraw node was removed, with its path
+    DeviceRemoved(String),
+}
+
+/// What a job overwrites. When the job queued right after another one has
+/// the same target, the first one is not sent to the EC at all.
+#[derive(PartialEq)]
+enum Target {
+    PowerMode(usize),
+    Fan(usize),
+    ZoneFan(usize, usize),
+    Idle(usize),
+    Bho,
+    AcState,
+    Screensaver,
+}
+
+/// Handle to the thread that owns the `DeviceManager`.
+///
+/// All EC I/O happens on that thread, one job at a time, so a slow EC only
+/// delays the jobs queued behind it and never the D-Bus signal handlers.
+#[derive(Clone)]
+pub struct DeviceWorker {
+    sender: Sender<Job>,
+}
+
+impl DeviceWorker {
+    pub fn start(manager: DeviceManager, idle: &'static Mutex<IdleWatch>, events: &'static EventBus) -> DeviceWorker {
+        let (sender, jobs) = mpsc::channel();
+        thread::spawn(move || run(manager, jobs, idle, events));
+        return DeviceWorker { sender };
+    }
+
+    /// Queues a job without waiting for it
+    pub fn send(&self, job: Job) {
+        if self.sender.send(job).is_err() {
+            error!("The device thread is gone");
+        }
+    }
+
+    /// Queues a client request and waits for its response
+    pub fn request(&self, command: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
+        let (reply, response) = mpsc::channel();
+        self.send(Job::Client(command, reply));
+        return response.recv().ok();
+    }
+}
+
+fn run(mut manager: DeviceManager, jobs: Receiver<Job>, idle: &'static Mutex<IdleWatch>, events: &'static EventBus) {
+    let mut queue: VecDeque<Job> = VecDeque::new();
+    sync_idle(&mut manager, idle);
+
+    while let Some(mut job) = next_job(&jobs, &mut queue) {
+        // Only the last of a burst of writes to the same setting is applied,
+        // the clients of the skipped ones get its response. A write that
+        // would be rejected doesn't replace the one before it.
+        let mut skipped = vec![];
+        while queue.front().map_or(false, |next| target(next).is_some() && target(next) == target(&job) && valid(&mut manager, next)) {
+            if let Some(next) = queue.pop_front() {
+                skipped.push(std::mem::replace(&mut job, next));
+            }
+        }
+        if !skipped.is_empty() {
+            info!("Coalesced {} queued writes", skipped.len());
+        }
+
+        let ac_online = manager.ac_online();
+        let attached = manager.device.is_some();
+        match job {
+            Job::Client(command, reply) => {
+                let event = command_event(&command);
+                let response = process_client_request(&mut manager, command);
+                if let Some(event) = event {
+                    if !matches!(response, comms::DaemonResponse::Error { .. }) {
+                        events.publish(event);
+                    }
+                }
+                for job in skipped {
+                    if let Job::Client(_, reply) = job {
+                        let _ = reply.send(response.clone());
+                    }
+                }
+                // The client may have given up already
+                let _ = reply.send(response);
+            },
+            Job::SetAcState(online) => manager.set_ac_state(online),
+            Job::RefreshAcState => manager.set_ac_state_get(),
+            Job::Screensaver(active) => {
+                manager.set_screensaver(active);
+                events.publish(comms::DaemonEvent::Screensaver { active });
+            },
+            Job::DeviceAdded => manager.device_added(),
+            Job::DeviceRemoved(path) => manager.device_removed(&path),
+        }
+
+        if manager.ac_online() != ac_online {
+            events.publish(comms::DaemonEvent::AcState { online: manager.ac_online() });
+        }
+        match (attached, manager.device.as_ref()) {
+            (false, Some(laptop)) => events.publish(comms::DaemonEvent::DeviceAttached { name: laptop.get_name() }),
+            (true, None) => events.publish(comms::DaemonEvent::DeviceDetached),
+            _ => {},
+        }
+
+        sync_idle(&mut manager, idle);
+    }
+}
+
+/// Next job to run, after moving whatever is waiting in the channel to the queue
+fn next_job(jobs: &Receiver<Job>, queue: &mut VecDeque<Job>) -> Option<Job> {
+    if queue.is_empty() {
+        queue.push_back(jobs.recv().ok()?);
+    }
+    queue.extend(jobs.try_iter());
+    return queue.pop_front();
+}
+
+/// Hands a new idle timeout over to the screensaver thread
+fn sync_idle(manager: &mut DeviceManager, idle: &Mutex<IdleWatch>) {
+    if manager.change_idle {
+        manager.change_idle = false;
+        let timeout = manager.idle_timeout();
+        if let Ok(mut idle) = idle.lock() {
+            idle.set_timeout(timeout);
+        }
+    }
+}
+
+fn target(job: &Job) -> Option<Target> {
+    match job {
+        Job::Client(command, _) => match *command {
+            comms::DaemonCommand::SetPowerMode { ac, .. } => Some(Target::PowerMode(ac)),
+            comms::DaemonCommand::SetFanSpeed { ac, .. } => Some(Target::Fan(ac)),
+            comms::DaemonCommand::SetZoneFanSpeed { ac, zone, .. } => Some(Target::ZoneFan(ac, zone)),
+            comms::DaemonCommand::SetIdle { ac, .. } => Some(Target::Idle(ac)),
+            comms::DaemonCommand::SetBatteryHealthOptimizer { .. } => Some(Target::Bho),
+            _ => None,
+        },
+        Job::SetAcState(_) | Job::RefreshAcState => Some(Target::AcState),
+        Job::Screensaver(_) => Some(Target::Screensaver),
+        Job::DeviceAdded | Job::DeviceRemoved(_) => None,
+    }
+}
+
+/// Whether `job` passes the checks `process_client_request` does before
+/// touching the config or the EC
+fn valid(manager: &mut DeviceManager, job: &Job) -> bool {
+    let command = match job {
+        Job::Client(command, _) => command,
+        _ => return true,
+    };
+    let result = match *command {
+        comms::DaemonCommand::SetPowerMode { ac, pwr, cpu, gpu } => manager.validate_power_mode(ac, pwr, cpu, gpu),
+        comms::DaemonCommand::SetFanSpeed { ac, rpm } => manager.validate_fan_rpm(ac, rpm),
+        comms::DaemonCommand::SetZoneFanSpeed { ac, zone, rpm } => manager.validate_zone_fan_rpm(ac, zone, rpm),
+        comms::DaemonCommand::SetIdle { ac, .. } => device::check_ac(ac),
+        comms::DaemonCommand::SetBatteryHealthOptimizer { threshold, .. } => manager.validate_bho(threshold),
+        _ => Ok(()),
+    };
+    return result.is_ok();
+}
+
+/// What subscribers are told once a command went through
+fn command_event(command: &comms::DaemonCommand) -> Option<comms::DaemonEvent> {
+    match *command {
+        comms::DaemonCommand::SetPowerMode { ac, pwr, cpu, gpu } => Some(comms::DaemonEvent::PowerMode { ac, pwr, cpu, gpu }),
+        comms::DaemonCommand::SetFanSpeed { ac, rpm } => Some(comms::DaemonEvent::FanSpeed { ac, rpm }),
+        comms::DaemonCommand::SetZoneFanSpeed { ac, zone, rpm } => Some(comms::DaemonEvent::ZoneFanSpeed { ac, zone, rpm }),
+        comms::DaemonCommand::SetIdle { ac, val } => Some(comms::DaemonEvent::Idle { ac, timeout: val }),
+        comms::DaemonCommand::SetBatteryHealthOptimizer { is_on, threshold } => {
+            Some(comms::DaemonEvent::BatteryHealthOptimizer { is_on, threshold })
+        },
+        _ => None,
+    }
+}
+
+fn process_client_request(d: &mut DeviceManager, cmd: comms::DaemonCommand) -> comms::DaemonResponse {
+    match cmd {
+        comms::DaemonCommand::SetPowerMode { ac, pwr, cpu, gpu } => {
+            respond(d.set_power_mode(ac, pwr, cpu, gpu), |_| comms::DaemonResponse::SetPowerMode { result: true })
+        },
+        comms::DaemonCommand::SetFanSpeed { ac, rpm } => {
+            respond(d.set_fan_rpm(ac, rpm), |_| comms::DaemonResponse::SetFanSpeed { result: true })
+        },
+        comms::DaemonCommand::SetIdle { ac, val } => {
+            respond(d.change_idle(ac, val), |_| comms::DaemonResponse::SetIdle { result: true })
+        }
+        comms::DaemonCommand::GetFanSpeed{ac} => respond(d.get_fan_rpm(ac), |rpm| comms::DaemonResponse::GetFanSpeed { rpm }),
+        comms::DaemonCommand::GetPwrLevel{ac} => respond(d.get_power_mode(ac), |pwr| comms::DaemonResponse::GetPwrLevel { pwr }),
+        comms::DaemonCommand::GetCPUBoost{ac} => respond(d.get_cpu_boost(ac), |cpu| comms::DaemonResponse::GetCPUBoost { cpu }),
+        comms::DaemonCommand::GetGPUBoost{ac} => respond(d.get_gpu_boost(ac), |gpu| comms::DaemonResponse::GetGPUBoost { gpu }),
+        comms::DaemonCommand::SetBatteryHealthOptimizer { is_on, threshold } => {
+            respond(d.set_bho_handler(is_on, threshold), |_| comms::DaemonResponse::SetBatteryHealthOptimizer { result: true })
+        }
+        comms::DaemonCommand::GetBatteryHealthOptimizer() => {
+            respond(d.get_bho_handler(), |result|
+                comms::DaemonResponse::GetBatteryHealthOptimizer {
+                    is_on: (result.0),
+                    threshold: (result.1)
+                }
+            )
+        }
+        comms::DaemonCommand::GetDeviceName => {
+            let name = d.device.as_ref().map(|device| device.get_name()).ok_or(comms::DeviceError::NoDevice);
+            respond(name, |name| comms::DaemonResponse::GetDeviceName { name })
+        }
+        comms::DaemonCommand::SetZoneFanSpeed { ac, zone, rpm } => {
+            respond(d.set_zone_fan_rpm(ac, zone, rpm), |_| comms::DaemonResponse::SetZoneFanSpeed { result: true })
+        }
+        comms::DaemonCommand::GetZoneFanSpeed { ac, zone } => {
+            respond(d.get_zone_fan_rpm(ac, zone), |rpm| comms::DaemonResponse::GetZoneFanSpeed { rpm })
+        }
+        comms::DaemonCommand::GetLimits => comms::DaemonResponse::GetLimits { limits: d.limits() },
+        comms::DaemonCommand::GetDeviceInfo => {
+            respond(d.get_device_info(), |info| comms::DaemonResponse::GetDeviceInfo { info })
+        }
+        comms::DaemonCommand::Hello { client, protocol } => {
+            info!("Client {} speaks protocol {}", client, protocol);
+            comms::hello_response()
+        }
+        // Streams and envelopes are dealt with by the connection thread, see `handle_data`
+        comms::DaemonCommand::Subscribe => comms::DaemonResponse::Error {
+            error: comms::DeviceError::InvalidArgument("Subscribe needs a connection of its own".to_string()),
+        },
+        comms::DaemonCommand::Request { .. } => comms::DaemonResponse::Error {
+            error: comms::DeviceError::InvalidArgument("requests can't be nested".to_string()),
+        },
+        comms::DaemonCommand::GetFeatures => {
+            respond(d.get_features(), |(features, unsupported)| comms::DaemonResponse::GetFeatures { features, unsupported })
+        }
+    }
+}
+
+/// Turns the outcome of a device operation into the response for the client
+fn respond<T>(result: Result<T, comms::DeviceError>, f: impl FnOnce(T) -> comms::DaemonResponse) -> comms::DaemonResponse {
+    match result {
+        Ok(value) => f(value),
+        Err(error) => {
+            warn!("Command failed: {}", error);
+            comms::DaemonResponse::Error { error }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RazerLaptop;
+    use crate::transport::MemoryTransport;
+    use service::{Capabilities, SupportedDevice};
+
+    fn manager() -> DeviceManager {
+        let info = SupportedDevice {
+            name: "Blade 15".to_string(),
+            vid: "1532".to_string(),
+            pid: "0000".to_string(),
+            features: vec!["bho".to_string()],
+            fan: vec![3500, 5000],
+            fan_zones: vec![],
+            interface: None,
+            capabilities: Capabilities::default(),
+            dmi: None,
+        };
+        let mut manager = DeviceManager::new();
+        manager.device = Some(RazerLaptop::new(&info, Box::new(MemoryTransport::new())));
+        return manager;
+    }
+
+    #[test]
+    fn invalid_write_does_not_replace_the_queued_one() {
+        let idle = Box::leak(Box::new(Mutex::new(IdleWatch::new())));
+        let events = Box::leak(Box::new(EventBus::new()));
+        let (sender, jobs) = mpsc::channel();
+        let (first_reply, first) = mpsc::channel();
+        let (second_reply, second) = mpsc::channel();
+        // Both are queued before the worker looks at the first one
+        let valid = comms::DaemonCommand::SetBatteryHealthOptimizer { is_on: false, threshold: 80 };
+        let invalid = comms::DaemonCommand::SetBatteryHealthOptimizer { is_on: true, threshold: 200 };
+        sender.send(Job::Client(valid, first_reply)).unwrap();
+        sender.send(Job::Client(invalid, second_reply)).unwrap();
+        drop(sender);
+
+        run(manager(), jobs, idle, events);
+
+        assert!(matches!(first.recv(), Ok(comms::DaemonResponse::SetBatteryHealthOptimizer { result: true })));
+        assert!(matches!(second.recv(), Ok(comms::DaemonResponse::Error { error: comms::DeviceError::InvalidArgument(_) })));
+    }
+}
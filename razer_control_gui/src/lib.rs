@@ -7,7 +7,127 @@ pub fn get_device_file_path() -> String {
     format!("{}/.local/share/razercontrol/laptops.json", home)
 }
 
-pub const DEVICE_FILE: &str = "/usr/share/razercontrol/laptops.json"; // Fallback for backwards compatibility
+pub const DEVICE_FILE: &str = "/usr/share/razercontrol/laptops.json"; // Packaged list, the first layer
+
+/// Drop-in directory, every *.json in it is loaded in file name order
+pub fn get_device_dir_path() -> String {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+    format!("{}/.local/share/razercontrol/laptops.d", home)
+}
+
+/// Points the DMI lookup to another sysfs tree than /sys, e.g. a copy of the
+/// one of a reporter's machine
+pub const SYSFS_ROOT_ENV: &str = "RAZER_LAPTOP_CONTROL_SYSFS";
+
+/// Feature names the daemon and the GUI know about
+pub const KNOWN_FEATURES: [&str; 4] = ["logo", "boost", "bho", "creator_mode"];
+
+/// An entry of the device database that was skipped
+#[derive(Debug, Clone)]
+pub struct EntryError {
+    /// Position in the JSON array
+    pub index: usize,
+    pub name: Option<String>,
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for EntryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "entry {} ({}): {}", self.index, name, self.problems.join(", ")),
+            None => write!(f, "entry {}: {}", self.index, self.problems.join(", ")),
+        }
+    }
+}
+
+/// Parses a device database. Entries that don't parse or don't make sense
+/// are left out and reported, only a file that isn't a JSON array fails.
+pub fn parse_devices(json: &str) -> Result<(Vec<SupportedDevice>, Vec<EntryError>), serde_json::Error> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(json)?;
+    let mut devices = vec![];
+    let mut errors = vec![];
+    for (index, entry) in entries.into_iter().enumerate() {
+        let name = entry.get("name").and_then(|n| n.as_str()).map(String::from);
+        let problems = match serde_json::from_value::<SupportedDevice>(entry) {
+            Ok(device) => {
+                let problems = device.validate();
+                if problems.is_empty() {
+                    devices.push(device);
+                    continue;
+                }
+                problems
+            },
+            Err(e) => vec![e.to_string()],
+        };
+        errors.push(EntryError { index, name, problems });
+    }
+
+    Ok((devices, errors))
+}
+
+/// The device database put together from all its layers
+#[derive(Debug, Default)]
+pub struct DeviceDatabase {
+    pub devices: Vec<SupportedDevice>,
+    /// Files that were read, in order
+    pub files: Vec<String>,
+    /// Skipped files and entries
+    pub errors: Vec<String>,
+}
+
+impl DeviceDatabase {
+    /// Loads the packaged list (`DEVICE_FILE`), the user file
+    /// (`get_device_file_path`) and the drop-ins (`get_device_dir_path`), in
+    /// that order. An entry replaces the earlier one with the same VID/PID.
+    pub fn load() -> DeviceDatabase {
+        let mut files = vec![DEVICE_FILE.to_string(), get_device_file_path()];
+        if let Ok(entries) = std::fs::read_dir(get_device_dir_path()) {
+            let mut drop_ins: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            drop_ins.sort();
+            files.extend(drop_ins);
+        }
+
+        let mut database = DeviceDatabase::default();
+        for file in files {
+            match load_devices(&file) {
+                Ok((devices, errors)) => {
+                    for error in errors {
+                        database.errors.push(format!("{}: {}", file, error));
+                    }
+                    for device in devices {
+                        database.add(device);
+                    }
+                    database.files.push(file);
+                },
+                // Every layer is optional
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => database.errors.push(format!("{}: {}", file, e)),
+            }
+        }
+
+        database
+    }
+
+    /// Adds an entry, replacing the one with the same VID/PID and `dmi` if any
+    pub fn add(&mut self, device: SupportedDevice) {
+        let ids = device.ids();
+        match self.devices.iter_mut().find(|d| d.ids() == ids && d.dmi == device.dmi) {
+            Some(existing) => *existing = device,
+            None => self.devices.push(device),
+        }
+    }
+}
+
+/// Reads a device database file, see `parse_devices`
+pub fn load_devices(path: &str) -> std::io::Result<(Vec<SupportedDevice>, Vec<EntryError>)> {
+    let json = std::fs::read_to_string(path)?;
+    parse_devices(&json).map_err(std::io::Error::from)
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SupportedDevice {
@@ -16,10 +136,198 @@ pub struct SupportedDevice {
     pub pid: String,
     pub features: Vec<String>,
     pub fan: Vec<u16>,
+    /// [min, max] of every fan that can be set on its own (CPU first, then
+    /// GPU). Empty when all the fans follow the same setting.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fan_zones: Vec<Vec<u16>>,
+    /// HID interface number that takes the EC feature reports. When missing
+    /// the daemon tries the interfaces of the device until the EC answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<i32>,
+    /// Limits that differ from what `features` implies
+    #[serde(default, skip_serializing_if = "Capabilities::is_empty")]
+    pub capabilities: Capabilities,
+    /// Part of the DMI product name, for PIDs shared by several models. The
+    /// entry whose `dmi` is in the product name wins over the others.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dmi: Option<String>,
+}
+
+/// What the firmware says about the machine, from /sys/class/dmi/id. Strings
+/// the firmware doesn't fill in are empty.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DmiInfo {
+    pub product_name: String,
+    pub product_version: String,
+    pub board_name: String,
+    pub bios_version: String,
+}
+
+impl DmiInfo {
+    /// Reads the DMI strings of the sysfs tree at `sysfs_root`
+    pub fn read(sysfs_root: &str) -> DmiInfo {
+        let read = |name: &str| {
+            std::fs::read_to_string(format!("{}/class/dmi/id/{}", sysfs_root, name))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+        DmiInfo {
+            product_name: read("product_name"),
+            product_version: read("product_version"),
+            board_name: read("board_name"),
+            bios_version: read("bios_version"),
+        }
+    }
+
+    /// Reads from `SYSFS_ROOT_ENV`, or /sys
+    pub fn from_env() -> DmiInfo {
+        let root = std::env::var(SYSFS_ROOT_ENV).unwrap_or_else(|_| "/sys".to_string());
+        DmiInfo::read(&root)
+    }
+}
+
+/// What the daemon tells about the attached laptop
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    /// `name` of the laptops.json entry in use
+    pub name: String,
+    pub vid: u16,
+    pub pid: u16,
+    pub dmi: DmiInfo,
+    /// As reported by the EC, None when it didn't answer
+    pub firmware: Option<String>,
+    pub serial: Option<String>,
+}
+
+/// Per-model overrides in laptops.json, anything missing is derived from
+/// the features (see `SupportedDevice::limits`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Power modes the EC accepts: 0 balanced, 1 gaming, 2 creator, 3 silent, 4 custom
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_modes: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cpu_boost: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_gpu_boost: Option<u8>,
+    /// [min, max] battery health optimizer threshold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bho_threshold: Option<[u8; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bho_step: Option<u8>,
+}
+
+impl Capabilities {
+    pub fn is_empty(&self) -> bool {
+        *self == Capabilities::default()
+    }
+}
+
+/// Effective limits of a model, what the daemon enforces and the clients offer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    pub power_modes: Vec<u8>,
+    pub max_cpu_boost: u8,
+    pub max_gpu_boost: u8,
+    pub bho_threshold: (u8, u8),
+    pub bho_step: u8,
+    /// [min, max] RPM of every independent fan zone
+    pub fan_zones: Vec<(u16, u16)>,
+}
+
+impl Limits {
+    pub fn has_power_mode(&self, mode: u8) -> bool {
+        self.power_modes.contains(&mode)
+    }
+
+    pub fn valid_bho_threshold(&self, threshold: u8) -> bool {
+        let (min, max) = self.bho_threshold;
+        (min..=max).contains(&threshold) && (threshold - min) % self.bho_step.max(1) == 0
+    }
+}
+
+impl Default for Limits {
+    /// What the daemon always accepted, used while no device is attached
+    fn default() -> Limits {
+        Limits {
+            power_modes: vec![0, 1, 2, 3, 4],
+            max_cpu_boost: 3,
+            max_gpu_boost: 2,
+            bho_threshold: (50, 80),
+            bho_step: 5,
+            fan_zones: vec![],
+        }
+    }
 }
 
 impl SupportedDevice {
 
+    /// VID and PID, None when they aren't hex numbers
+    pub fn ids(&self) -> Option<(u16, u16)> {
+        let vid = u16::from_str_radix(&self.vid, 16).ok()?;
+        let pid = u16::from_str_radix(&self.pid, 16).ok()?;
+        Some((vid, pid))
+    }
+
+    /// Everything that makes this entry unusable, empty when it is fine
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.name.trim().is_empty() {
+            problems.push("empty name".to_string());
+        }
+        if u16::from_str_radix(&self.vid, 16).is_err() {
+            problems.push(format!("invalid VID {:?}", self.vid));
+        }
+        if u16::from_str_radix(&self.pid, 16).is_err() {
+            problems.push(format!("invalid PID {:?}", self.pid));
+        }
+        if self.dmi.as_ref().is_some_and(|dmi| dmi.trim().is_empty()) {
+            problems.push("empty dmi".to_string());
+        }
+        if let Some(problem) = check_range("fan", &self.fan) {
+            problems.push(problem);
+        }
+        if self.fan_zones.len() > 2 {
+            problems.push(format!("{} fan zones, the EC has 2", self.fan_zones.len()));
+        }
+        for (zone, range) in self.fan_zones.iter().enumerate() {
+            if let Some(problem) = check_range(&format!("fan_zones[{}]", zone), range) {
+                problems.push(problem);
+            }
+        }
+
+        let caps = &self.capabilities;
+        if let Some(modes) = &caps.power_modes {
+            if modes.is_empty() || modes.iter().any(|mode| *mode > 4) {
+                problems.push(format!("power_modes {:?} must be some of 0 to 4", modes));
+            }
+        }
+        if caps.max_cpu_boost.is_some_and(|boost| boost > 3) {
+            problems.push("max_cpu_boost is above 3".to_string());
+        }
+        if caps.max_gpu_boost.is_some_and(|boost| boost > 2) {
+            problems.push("max_gpu_boost is above 2".to_string());
+        }
+        if let Some([min, max]) = caps.bho_threshold {
+            if min > max || max > 100 {
+                problems.push(format!("bho_threshold [{}, {}] is not a range of percentages", min, max));
+            }
+        }
+        if caps.bho_step == Some(0) {
+            problems.push("bho_step is 0".to_string());
+        }
+
+        problems
+    }
+
+    /// Whether `dmi` is part of the product name, false for entries without it
+    pub fn matches_dmi(&self, info: &DmiInfo) -> bool {
+        match &self.dmi {
+            Some(dmi) => info.product_name.to_lowercase().contains(&dmi.to_lowercase()),
+            None => false,
+        }
+    }
+
     pub fn has_feature(&self, feature: &str) -> bool {
         self.features.iter().any(|f| f == feature)
     }
@@ -32,4 +340,50 @@ impl SupportedDevice {
         self.has_feature("logo")
     }
 
+    /// Number of fans that can be controlled independently
+    pub fn fan_zone_count(&self) -> usize {
+        self.fan_zones.len().max(1)
+    }
+
+    /// Limits of this model, `capabilities` first and then the features
+    pub fn limits(&self) -> Limits {
+        let caps = &self.capabilities;
+        let defaults = Limits::default();
+        let power_modes = caps.power_modes.clone().unwrap_or_else(|| {
+            defaults.power_modes.iter().copied()
+                .filter(|mode| *mode != 2 || self.has_feature("creator_mode"))
+                .collect()
+        });
+        let fan_zones = (0..self.fan_zone_count())
+            .filter_map(|zone| self.fan_range(zone))
+            .collect();
+
+        Limits {
+            power_modes,
+            max_cpu_boost: caps.max_cpu_boost.unwrap_or(if self.can_boost() { 3 } else { 2 }),
+            max_gpu_boost: caps.max_gpu_boost.unwrap_or(defaults.max_gpu_boost),
+            bho_threshold: caps.bho_threshold.map_or(defaults.bho_threshold, |[min, max]| (min, max)),
+            bho_step: caps.bho_step.unwrap_or(defaults.bho_step),
+            fan_zones,
+        }
+    }
+
+    /// [min, max] RPM of a fan zone, falls back to `fan`
+    pub fn fan_range(&self, zone: usize) -> Option<(u16, u16)> {
+        let range = self.fan_zones.get(zone).unwrap_or(&self.fan);
+        match range.as_slice() {
+            [min, max] => Some((*min, *max)),
+            _ => None,
+        }
+    }
+
+}
+
+/// A [min, max] pair of RPM
+fn check_range(field: &str, range: &[u16]) -> Option<String> {
+    match range {
+        [min, max] if min > max => Some(format!("{} range [{}, {}] is inverted", field, min, max)),
+        [_, _] => None,
+        _ => Some(format!("{} must be [min, max], got {:?}", field, range)),
+    }
 }
@@ -36,6 +36,19 @@ pub fn crash_with_msg(msg: impl AsRef<str>) -> ! {
     std::process::exit(1);
 }
 
+/// Shows an error without closing the application
+pub fn show_error(msg: impl AsRef<str>) {
+    let msg_box = MessageDialog::new::<ApplicationWindow>(
+        None, DialogFlags::MODAL,
+        gtk::MessageType::Error, gtk::ButtonsType::Ok,
+        msg.as_ref()
+    );
+    msg_box.set_title("Error");
+
+    let _response = msg_box.run();
+    msg_box.close();
+}
+
 fn show_msg(msg: impl AsRef<str>) {
     let msg = format!("{}.\n\nThis is an alpha!", msg.as_ref());
 
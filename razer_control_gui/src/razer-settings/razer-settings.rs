@@ -17,57 +17,130 @@ mod error_handling;
 mod widgets;
 mod util;
 
-use service::SupportedDevice;
+use service::{DeviceInfo, Limits, SupportedDevice};
 use error_handling::*;
 use widgets::*;
 use util::*;
 
-fn send_data(opt: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
-    match comms::try_bind() {
-        Ok(socket) => comms::send_to_daemon(opt, socket),
+thread_local! {
+    /// Kept open between the getters, see `comms::Connection`
+    static CONNECTION: std::cell::RefCell<Option<comms::Connection>> = std::cell::RefCell::new(None);
+}
+
+/// Sends a command, what to do when it fails is up to the caller
+fn send_data(opt: comms::DaemonCommand) -> Result<comms::DaemonResponse, String> {
+    let response = CONNECTION.with(|connection| {
+        let mut connection = connection.borrow_mut();
+        if let Some(conn) = connection.as_mut() {
+            match conn.request(opt.clone()) {
+                Ok(response) => return Ok(response),
+                // The daemon drops idle connections and may have been
+                // restarted, try again on a fresh one
+                Err(_) => *connection = None,
+            }
+        }
+        let mut conn = comms::Connection::open()?;
+        let response = conn.request(opt);
+        if response.is_ok() {
+            *connection = Some(conn);
+        }
+        return response;
+    });
+
+    match response {
+        Ok(comms::DaemonResponse::Error { error }) => Err(format!("The daemon reported an error: {error}")),
+        Ok(response) => Ok(response),
         Err(error) if error.kind() == ErrorKind::NotFound => {
             crash_with_msg("Can't connect to the daemon");
         }
-        Err(error) => {
-            println!("Error opening socket: {error}");
-            None
-        }
+        Err(error) => Err(format!("Error talking to the daemon: {error}")),
     }
 }
 
-fn get_device_name() -> Option<String> {
+/// Tells about a setting the daemon didn't apply, the widgets are then
+/// reset to what it reads back
+fn show_failure(result: Result<bool, String>) {
+    if let Err(error) = result {
+        show_error(error);
+    }
+}
+
+fn get_device_name() -> Result<String, String> {
     let response = send_data(comms::DaemonCommand::GetDeviceName)?;
 
     use comms::DaemonResponse::*;
     match response {
         GetDeviceName { name } => {
-            Some(name)
+            Ok(name)
         }
         response => {
             // This should not happen
-            println!("Instead of GetDeviceName got {response:?}");
-            None
+            Err(format!("Instead of GetDeviceName got {response:?}"))
         }
     }
 }
 
-fn get_bho() -> Option<(bool, u8)> {
+fn get_features() -> Result<Vec<String>, String> {
+    let response = send_data(comms::DaemonCommand::GetFeatures)?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetFeatures { features, .. } => {
+            Ok(features)
+        }
+        response => {
+            // This should not happen
+            Err(format!("Instead of GetFeatures got {response:?}"))
+        }
+    }
+}
+
+fn get_limits() -> Result<Limits, String> {
+    let response = send_data(comms::DaemonCommand::GetLimits)?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetLimits { limits } => {
+            Ok(limits)
+        }
+        response => {
+            // This should not happen
+            Err(format!("Instead of GetLimits got {response:?}"))
+        }
+    }
+}
+
+fn get_device_info() -> Result<DeviceInfo, String> {
+    let response = send_data(comms::DaemonCommand::GetDeviceInfo)?;
+
+    use comms::DaemonResponse::*;
+    match response {
+        GetDeviceInfo { info } => {
+            Ok(info)
+        }
+        response => {
+            // This should not happen
+            Err(format!("Instead of GetDeviceInfo got {response:?}"))
+        }
+    }
+}
+
+fn get_bho() -> Result<(bool, u8), String> {
     let response = send_data(comms::DaemonCommand::GetBatteryHealthOptimizer())?;
 
     use comms::DaemonResponse::*;
     match response {
         GetBatteryHealthOptimizer { is_on, threshold } => {
-            Some((is_on, threshold))
+            Ok((is_on, threshold))
         }
         response => {
             // This should not happen
-            println!("Instead of GetBatteryHealthOptimizer got {response:?}");
-            None
+            Err(format!("Instead of GetBatteryHealthOptimizer got {response:?}"))
         }
     }
 }
 
-fn set_bho(is_on: bool, threshold: u8) -> Option<bool> {
+fn set_bho(is_on: bool, threshold: u8) -> Result<bool, String> {
     let response = send_data(comms::DaemonCommand::SetBatteryHealthOptimizer {
         is_on, threshold
     })?;
@@ -75,12 +148,11 @@ fn set_bho(is_on: bool, threshold: u8) -> Option<bool> {
     use comms::DaemonResponse::*;
     match response {
         SetBatteryHealthOptimizer { result } => {
-            Some(result)
+            Ok(result)
         }
         response => {
             // This should not happen
-            println!("Instead of SetBatteryHealthOptimizer got {response:?}");
-            None
+            Err(format!("Instead of SetBatteryHealthOptimizer got {response:?}"))
         }
     }
 }
@@ -90,7 +162,7 @@ fn set_bho(is_on: bool, threshold: u8) -> Option<bool> {
 
 
 
-fn get_power(ac: bool) -> Option<(u8, u8, u8)> {
+fn get_power(ac: bool) -> Result<(u8, u8, u8), String> {
     let ac = if ac { 1 } else { 0 };
     let mut result = (0, 0, 0);
 
@@ -102,8 +174,7 @@ fn get_power(ac: bool) -> Option<(u8, u8, u8)> {
         }
         response => {
             // This should not happen
-            println!("Instead of GetPwrLevel got {response:?}");
-            return None
+            return Err(format!("Instead of GetPwrLevel got {response:?}"))
         }
     }
 
@@ -115,8 +186,7 @@ fn get_power(ac: bool) -> Option<(u8, u8, u8)> {
         }
         response => {
             // This should not happen
-            println!("Instead of GetCPUBoost got {response:?}");
-            return None
+            return Err(format!("Instead of GetCPUBoost got {response:?}"))
         }
     }
 
@@ -128,15 +198,14 @@ fn get_power(ac: bool) -> Option<(u8, u8, u8)> {
         }
         response => {
             // This should not happen
-            println!("Instead of GetGPUBoost got {response:?}");
-            return None
+            return Err(format!("Instead of GetGPUBoost got {response:?}"))
         }
     }
 
-    Some(result)
+    Ok(result)
 }
 
-fn set_power(ac: bool, power: (u8, u8, u8)) -> Option<bool> {
+fn set_power(ac: bool, power: (u8, u8, u8)) -> Result<bool, String> {
     let ac = if ac { 1 } else { 0 };
     let response = send_data(comms::DaemonCommand::SetPowerMode {
         ac, pwr: power.0, cpu: power.1, gpu: power.2 }
@@ -145,46 +214,53 @@ fn set_power(ac: bool, power: (u8, u8, u8)) -> Option<bool> {
     use comms::DaemonResponse::*;
     match response {
         SetPowerMode { result } => {
-            Some(result)
+            Ok(result)
         }
         response => {
             // This should not happen
-            println!("Instead of SetPowerMode got {response:?}");
-            None
+            Err(format!("Instead of SetPowerMode got {response:?}"))
         }
     }
 }
 
-fn get_fan_speed(ac: bool) -> Option<i32> {
+/// Reads the fan speed, of a single fan zone when `zone` is given
+fn get_fan_speed(ac: bool, zone: Option<usize>) -> Result<i32, String> {
     let ac = if ac { 1 } else { 0 };
-    let response = send_data(comms::DaemonCommand::GetFanSpeed{ ac })?;
+    let command = match zone {
+        Some(zone) => comms::DaemonCommand::GetZoneFanSpeed{ ac, zone },
+        None => comms::DaemonCommand::GetFanSpeed{ ac },
+    };
+    let response = send_data(command)?;
 
     use comms::DaemonResponse::*;
     match response {
-        GetFanSpeed { rpm } => {
-            Some(rpm)
+        GetFanSpeed { rpm } | GetZoneFanSpeed { rpm } => {
+            Ok(rpm)
         }
         response => {
             // This should not happen
-            println!("Instead of GetFanSpeed got {response:?}");
-            None
+            Err(format!("Instead of GetFanSpeed got {response:?}"))
         }
     }
 }
 
-fn set_fan_speed(ac: bool, value: i32) -> Option<bool> {
+/// Sets the fan speed, of a single fan zone when `zone` is given
+fn set_fan_speed(ac: bool, zone: Option<usize>, value: i32) -> Result<bool, String> {
     let ac = if ac { 1 } else { 0 };
-    let response = send_data(comms::DaemonCommand::SetFanSpeed{ ac, rpm: value })?;
+    let command = match zone {
+        Some(zone) => comms::DaemonCommand::SetZoneFanSpeed{ ac, zone, rpm: value },
+        None => comms::DaemonCommand::SetFanSpeed{ ac, rpm: value },
+    };
+    let response = send_data(command)?;
 
     use comms::DaemonResponse::*;
     match response {
-        SetFanSpeed { result } => {
-            Some(result)
+        SetFanSpeed { result } | SetZoneFanSpeed { result } => {
+            Ok(result)
         }
         response => {
             // This should not happen
-            println!("Instead of SetFanSpeed got {response:?}");
-            None
+            Err(format!("Instead of SetFanSpeed got {response:?}"))
         }
     }
 }
@@ -193,11 +269,15 @@ fn main() {
     setup_panic_hook();
     gtk::init().or_crash("Failed to initialize GTK.");
 
-    let device_file = std::fs::read_to_string(service::get_device_file_path())
-        .or_crash("Failed to read the device file");
-    let devices: Vec<SupportedDevice> = serde_json::from_str(&device_file)
-        .or_crash("Failed to parse the device file");
+    let database = service::DeviceDatabase::load();
+    for error in &database.errors {
+        eprintln!("Skipping {}", error);
+    }
+    let devices = database.devices;
 
+    // Refuses a daemon we can't talk to, and tells what it can do
+    let commands = comms::handshake("razer-settings")
+        .unwrap_or_else(|msg| crash_with_msg(msg));
     let device_name = get_device_name()
         .or_crash("Failed to get device name");
 
@@ -209,8 +289,14 @@ fn main() {
         // For now we get the device from the device name. One is duplicated but
         // its settings are the same.
         // TODO: Document this or make it more robust
-        let device = devices.iter().find(|d| d.name == device_name)
-            .or_crash("Failed to get device info");
+        let mut device = devices.iter().find(|d| d.name == device_name)
+            .or_crash("Failed to get device info")
+            .clone();
+        // The daemon checks the declared features against the EC
+        if let Ok(features) = get_features() {
+            device.features = features;
+        }
+        let limits = get_limits().unwrap_or_else(|_| device.limits());
 
         let window = ApplicationWindow::builder()
             .application(app)
@@ -220,9 +306,10 @@ fn main() {
             .window_position(gtk::WindowPosition::Center)
             .build();
 
-        let ac_settings_page = make_page(true, device.clone());
-        let battery_settings_page = make_page(false, device.clone());
-        let about_page = make_about_page(device.clone());
+        let ac_settings_page = make_page(true, &limits);
+        let battery_settings_page = make_page(false, &limits);
+        let info = if commands.iter().any(|c| c == "GetDeviceInfo") { get_device_info().ok() } else { None };
+        let about_page = make_about_page(device.clone(), info);
 
         let stack = Stack::new();
         stack.set_transition_type(gtk::StackTransitionType::SlideLeftRight);
@@ -232,7 +319,7 @@ fn main() {
         
         // Only add Battery Health tab if device supports BHO
         if device.has_feature("bho") {
-            let general_page = make_general_page();
+            let general_page = make_general_page(&limits);
             stack.add_titled(&general_page.master_container, "Battery Health", "Battery Health");
         }
         
@@ -284,38 +371,47 @@ fn main() {
     app.run();
 }
 
-fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
-    let fan_speed = get_fan_speed(ac).or_crash("Error reading fan speed");
-    let power = get_power(ac);
+/// Names of the power modes, indexed by mode number
+const POWER_MODES: [&str; 5] = ["Balanced", "Gaming", "Creator", "Silent", "Custom"];
+/// Names of the CPU/GPU boost levels, indexed by level
+const BOOST_LEVELS: [&str; 4] = ["Low", "Medium", "High", "Boost"];
+
+fn active_power_mode(power_profile: &ComboBoxText) -> u8 {
+    return power_profile.active_id()
+        .and_then(|id| id.parse::<u8>().ok())
+        .or_crash("Illegal state");
+}
 
-    let min_fan_speed = *device.fan.get(0)
-        .or_crash("Invalid fan values") as f64;
-    let max_fan_speed = *device.fan.get(1)
-        .or_crash("Invalid fan values") as f64;
+fn make_page(ac: bool, limits: &Limits) -> SettingsPage {
+    // Left out when the daemon can't tell, e.g. without a device
+    let power = get_power(ac);
+    if let Err(error) = &power {
+        println!("No power section: {error}");
+    }
 
     let settings_page = SettingsPage::new();
 
 
     // Power section
-    if let Some(power) = power {
+    if let Ok(power) = power {
         let settings_section = settings_page.add_section(Some("Power"));
             let label = Label::new(Some("Power Profile"));
             let power_profile = ComboBoxText::new();
-                power_profile.append_text("Balanced");
-                power_profile.append_text("Gaming");
-                power_profile.append_text("Creator");
-                power_profile.append_text("Silent");
-                power_profile.append_text("Custom");
-                power_profile.set_active(Some(power.0 as u32));
+                // Not every model has every mode, the ids are the mode numbers
+                for mode in &limits.power_modes {
+                    if let Some(name) = POWER_MODES.get(*mode as usize) {
+                        power_profile.append(Some(&mode.to_string()), name);
+                    }
+                }
+                power_profile.set_active_id(Some(&power.0.to_string()));
                 power_profile.set_width_request(100);
         let row = SettingsRow::new(&label, &power_profile);
         settings_section.add_row(&row.master_container);
             let label = Label::new(Some("CPU Boost"));
             let cpu_boost = ComboBoxText::new();
-                cpu_boost.append_text("Low");
-                cpu_boost.append_text("Medium");
-                cpu_boost.append_text("High");
-                if device.can_boost() { cpu_boost.append_text("Boost") };
+                for name in BOOST_LEVELS.iter().take(limits.max_cpu_boost as usize + 1) {
+                    cpu_boost.append_text(name);
+                }
                 cpu_boost.set_active(Some(power.1 as u32));
                 cpu_boost.set_width_request(100);
         let row = SettingsRow::new(&label, &cpu_boost);
@@ -323,9 +419,9 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
         settings_section.add_row(cpu_boost_row);
             let label = Label::new(Some("GPU Boost"));
             let gpu_boost = ComboBoxText::new();
-                gpu_boost.append_text("Low");
-                gpu_boost.append_text("Medium");
-                gpu_boost.append_text("High");
+                for name in BOOST_LEVELS.iter().take(limits.max_gpu_boost as usize + 1) {
+                    gpu_boost.append_text(name);
+                }
                 gpu_boost.set_active(Some(power.2 as u32));
                 gpu_boost.set_width_request(100);
         let row = SettingsRow::new(&label, &gpu_boost);
@@ -349,13 +445,16 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
             @weak cpu_boost_row, @weak gpu_boost_row
             =>
             move |power_profile| {
-                let profile = power_profile.active().or_crash("Illegal state") as u8;
+                let profile = active_power_mode(&power_profile);
                 let cpu     = cpu_boost.active().or_crash("Illegal state") as u8;
                 let gpu     = gpu_boost.active().or_crash("Illegal state") as u8;
-                set_power(ac, (profile, cpu, gpu)).or_crash("Error setting power");
+                show_failure(set_power(ac, (profile, cpu, gpu)));
 
-                let power = get_power(ac).or_crash("Error reading power");
-                power_profile.set_active(Some(power.0 as u32));
+                let power = match get_power(ac) {
+                    Ok(power) => power,
+                    Err(_) => return,
+                };
+                power_profile.set_active_id(Some(&power.0.to_string()));
                 cpu_boost.set_active(Some(power.1 as u32));
                 gpu_boost.set_active(Some(power.2 as u32));
 
@@ -372,13 +471,16 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
             @weak power_profile, @weak gpu_boost
             =>
             move |cpu_boost| {
-                let profile = power_profile.active().or_crash("Illegal state") as u8;
+                let profile = active_power_mode(&power_profile);
                 let cpu     = cpu_boost.active().or_crash("Illegal state") as u8;
                 let gpu     = gpu_boost.active().or_crash("Illegal state") as u8;
-                set_power(ac, (profile, cpu, gpu)).or_crash("Error setting power");
+                show_failure(set_power(ac, (profile, cpu, gpu)));
 
-                let power = get_power(ac).or_crash("Error reading power");
-                power_profile.set_active(Some(power.0 as u32));
+                let power = match get_power(ac) {
+                    Ok(power) => power,
+                    Err(_) => return,
+                };
+                power_profile.set_active_id(Some(&power.0.to_string()));
                 cpu_boost.set_active(Some(power.1 as u32));
                 gpu_boost.set_active(Some(power.2 as u32));
             }
@@ -387,13 +489,16 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
             @weak power_profile, @weak cpu_boost
             =>
             move |gpu_boost| {
-                let profile = power_profile.active().or_crash("Illegal state") as u8;
+                let profile = active_power_mode(&power_profile);
                 let cpu     = cpu_boost.active().or_crash("Illegal state") as u8;
                 let gpu     = gpu_boost.active().or_crash("Illegal state") as u8;
-                set_power(ac, (profile, cpu, gpu)).or_crash("Error setting power");
+                show_failure(set_power(ac, (profile, cpu, gpu)));
 
-                let power = get_power(ac).or_crash("Error reading power");
-                power_profile.set_active(Some(power.0 as u32));
+                let power = match get_power(ac) {
+                    Ok(power) => power,
+                    Err(_) => return,
+                };
+                power_profile.set_active_id(Some(&power.0.to_string()));
                 cpu_boost.set_active(Some(power.1 as u32));
                 gpu_boost.set_active(Some(power.2 as u32));
             }
@@ -401,7 +506,37 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
     }
 
     // Fan Speed Section
-    let settings_section = settings_page.add_section(Some("Fan Speed"));
+    if limits.fan_zones.len() > 1 {
+        add_fan_section(&settings_page, ac, limits, Some(0), "CPU Fan");
+        add_fan_section(&settings_page, ac, limits, Some(1), "GPU Fan");
+    } else {
+        add_fan_section(&settings_page, ac, limits, None, "Fan Speed");
+    }
+
+
+    settings_page
+}
+
+/// Auto switch and speed slider for all the fans, or a single fan zone
+fn add_fan_section(settings_page: &SettingsPage, ac: bool, limits: &Limits, zone: Option<usize>, title: &str) {
+    // A zone falls back to the first range, without any (no device attached)
+    // there is nothing to offer
+    let range = zone.and_then(|zone| limits.fan_zones.get(zone)).or_else(|| limits.fan_zones.first());
+    let (min_fan_speed, max_fan_speed) = match range {
+        Some(range) => *range,
+        None => return,
+    };
+    let fan_speed = match get_fan_speed(ac, zone) {
+        Ok(fan_speed) => fan_speed,
+        Err(error) => {
+            println!("No {title} section: {error}");
+            return;
+        }
+    };
+    let min_fan_speed = min_fan_speed as f64;
+    let max_fan_speed = max_fan_speed as f64;
+
+    let settings_section = settings_page.add_section(Some(title));
         let label = Label::new(Some("Auto"));
         let switch = Switch::new();
         let auto = fan_speed == 0;
@@ -415,8 +550,11 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
         scale.set_width_request(100);
         scale.connect_change_value(clone!(@weak switch => @default-return gtk::glib::Propagation::Stop, move |scale, stype, value| {
             let value = value.clamp(min_fan_speed, max_fan_speed);
-            set_fan_speed(ac, value as i32).or_crash("Error setting fan speed");
-            let fan_speed = get_fan_speed(ac).or_crash("Error reading fan speed");
+            show_failure(set_fan_speed(ac, zone, value as i32));
+            let fan_speed = match get_fan_speed(ac, zone) {
+                Ok(fan_speed) => fan_speed,
+                Err(_) => return gtk::glib::Propagation::Stop,
+            };
             let auto = fan_speed == 0;
             scale.set_value(fan_speed as f64);
             scale.set_sensitive(!auto);
@@ -424,8 +562,11 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
             return gtk::glib::Propagation::Stop;
         }));
         switch.connect_changed_active(clone!(@weak scale => move |switch| {
-            set_fan_speed(ac, if switch.is_active() { 0 } else { min_fan_speed as i32 }).or_crash("Error setting fan speed");
-            let fan_speed = get_fan_speed(ac).or_crash("Error reading fan speed");
+            show_failure(set_fan_speed(ac, zone, if switch.is_active() { 0 } else { min_fan_speed as i32 }));
+            let fan_speed = match get_fan_speed(ac, zone) {
+                Ok(fan_speed) => fan_speed,
+                Err(_) => return,
+            };
             let auto = fan_speed == 0;
             scale.set_value(fan_speed as f64);
             scale.set_sensitive(!auto);
@@ -433,19 +574,19 @@ fn make_page(ac: bool, device: SupportedDevice) -> SettingsPage {
         }));
     let row = SettingsRow::new(&label, &scale);
     settings_section.add_row(&row.master_container);
-
-
-    settings_page
 }
 
-fn make_general_page() -> SettingsPage {
+fn make_general_page(limits: &Limits) -> SettingsPage {
     let bho = get_bho();
+    if let Err(error) = &bho {
+        println!("No Battery Health Optimizer section: {error}");
+    }
 
     let page = SettingsPage::new();
 
 
     // Battery Health Optimizer section
-    if let Some(bho) = bho {
+    if let Ok(bho) = bho {
         let settings_section = page.add_section(Some("Battery Health Optimizer"));
             let label = Label::new(Some("Enable Battery Health Optimizer"));
             let switch = Switch::new();
@@ -453,16 +594,23 @@ fn make_general_page() -> SettingsPage {
         let row = SettingsRow::new(&label, &switch);
         settings_section.add_row(&row.master_container);
             let label = Label::new(Some("Theshold"));
-            let scale = Scale::with_range(gtk::Orientation::Horizontal, 65f64, 80f64, 1f64);
+            let (min_threshold, max_threshold) = (limits.bho_threshold.0 as f64, limits.bho_threshold.1 as f64);
+            let step = limits.bho_step.max(1) as f64;
+            // The EC only takes thresholds on the step
+            let snap = move |value: f64| ((value.clamp(min_threshold, max_threshold) - min_threshold) / step).round() * step + min_threshold;
+            let scale = Scale::with_range(gtk::Orientation::Horizontal, min_threshold, max_threshold, limits.bho_step as f64);
             scale.set_value(bho.1 as f64);
             scale.set_width_request(100);
             scale.connect_change_value(clone!(@weak switch => @default-return gtk::glib::Propagation::Stop, move |scale, stype, value| {
                 let is_on = switch.is_active();
-                let threshold = value.clamp(50f64, 80f64) as u8;
+                let threshold = snap(value) as u8;
 
-                set_bho(is_on, threshold).or_crash("Error setting bho");
+                show_failure(set_bho(is_on, threshold));
 
-                let (is_on, threshold) = get_bho().or_crash("Error reading bho");
+                let (is_on, threshold) = match get_bho() {
+                    Ok(bho) => bho,
+                    Err(_) => return gtk::glib::Propagation::Stop,
+                };
                 
                 scale.set_value(threshold as f64);
                 scale.set_visible(is_on);
@@ -473,12 +621,14 @@ fn make_general_page() -> SettingsPage {
             scale.set_sensitive(bho.0);
             switch.connect_changed_active(clone!(@weak scale => move |switch| {
                 let is_on = switch.is_active();
-                let threshold = scale.value().clamp(50f64, 80f64) as u8;
+                let threshold = snap(scale.value()) as u8;
                 
-                set_bho(is_on, threshold); // Ignoramos errores ya que leemos
-                                           // el resultado de vuelta
+                show_failure(set_bho(is_on, threshold));
 
-                let (is_on, threshold) = get_bho().or_crash("Error reading bho");
+                let (is_on, threshold) = match get_bho() {
+                    Ok(bho) => bho,
+                    Err(_) => return,
+                };
                 
                 scale.set_value(threshold as f64);
                 scale.set_visible(is_on);
@@ -491,7 +641,7 @@ fn make_general_page() -> SettingsPage {
     page
 }
 
-fn make_about_page(device: SupportedDevice) -> SettingsPage {
+fn make_about_page(device: SupportedDevice, info: Option<DeviceInfo>) -> SettingsPage {
     let page = SettingsPage::new();
 
     // About page
@@ -522,5 +672,24 @@ fn make_about_page(device: SupportedDevice) -> SettingsPage {
     let row = SettingsRow::new(&label, &features_label);
     settings_section.add_row(&row.master_container);
 
+    if let Some(info) = info {
+        let details = [
+            ("USB ID", format!("{:04x}:{:04x}", info.vid, info.pid)),
+            ("Product", info.dmi.product_name),
+            ("Product version", info.dmi.product_version),
+            ("Board", info.dmi.board_name),
+            ("BIOS version", info.dmi.bios_version),
+            ("EC firmware", info.firmware.unwrap_or_else(|| "unknown".to_string())),
+            ("Serial number", info.serial.unwrap_or_else(|| "unknown".to_string())),
+        ];
+        for (name, value) in details {
+            let label = Label::new(Some(name));
+            let value_label = Label::new(Some(&value));
+            value_label.set_selectable(true);
+            let row = SettingsRow::new(&label, &value_label);
+            settings_section.add_row(&row.master_container);
+        }
+    }
+
     page
 }
@@ -0,0 +1,148 @@
+use crate::command::{BoostTarget, EcCommand, EcResponse, SERIAL_SIZE};
+use crate::packet::RazerPacket;
+use service::Limits;
+
+/// Firmware version the simulator reports
+const SIM_FIRMWARE: (u8, u8) = (1, 0);
+
+/// Software model of the embedded controller of a Blade.
+///
+/// Only knows about the power/fan/boost (0x0d) and battery health optimizer
+/// (0x07) command classes, plus the firmware and serial queries (0x00). Everything else is answered with
+/// `RAZER_CMD_NOT_SUPPORTED`, as are the features the emulated model lacks
+/// and the values outside of its limits.
+pub struct SimulatedEc {
+    features: Vec<String>,
+    limits: Limits,
+    power: [u8; 2],
+    manual_fan: [bool; 2],
+    fan_rpm: [u16; 2],
+    cpu_boost: u8,
+    gpu_boost: u8,
+    bho: (bool, u8),
+    serial: [u8; SERIAL_SIZE],
+}
+
+impl SimulatedEc {
+    pub fn new(features: Vec<String>, limits: Limits, serial: &str) -> SimulatedEc {
+        let mut serial_bytes = [0u8; SERIAL_SIZE];
+        for (byte, c) in serial_bytes.iter_mut().zip(serial.bytes()) {
+            *byte = c;
+        }
+        return SimulatedEc {
+            features,
+            limits,
+            power: [0, 0],
+            manual_fan: [false, false],
+            fan_rpm: [0, 0],
+            cpu_boost: 1,
+            gpu_boost: 0,
+            bho: (false, 80),
+            serial: serial_bytes,
+        };
+    }
+
+    fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Builds the answer for `request`, including its CRC
+    pub fn handle(&mut self, request: &RazerPacket) -> RazerPacket {
+        let mut response = request.clone();
+        response.status = match EcCommand::decode(request) {
+            Some(command) => match self.execute(command) {
+                Ok(answer) => {
+                    command.encode_response(&answer, &mut response);
+                    RazerPacket::RAZER_CMD_SUCCESSFUL
+                },
+                Err(status) => status,
+            },
+            None => RazerPacket::RAZER_CMD_NOT_SUPPORTED,
+        };
+        response.calc_crc();
+        return response;
+    }
+
+    /// Applies a command, errors are the status to answer with
+    fn execute(&mut self, command: EcCommand) -> Result<EcResponse, u8> {
+        match command {
+            EcCommand::GetPowerMode { zone } => {
+                let z = zone_index(zone)?;
+                Ok(EcResponse::PowerMode { zone, mode: self.power[z], manual_fan: self.manual_fan[z] })
+            },
+            EcCommand::SetPowerMode { zone, mode, manual_fan } => {
+                let z = zone_index(zone)?;
+                if mode > 4 {
+                    return Err(RazerPacket::RAZER_CMD_FAILURE);
+                }
+                if !self.limits.has_power_mode(mode) {
+                    return Err(RazerPacket::RAZER_CMD_NOT_SUPPORTED);
+                }
+                self.power[z] = mode;
+                self.manual_fan[z] = manual_fan;
+                Ok(EcResponse::Done)
+            },
+            EcCommand::GetFanRpm { zone } => {
+                let z = zone_index(zone)?;
+                Ok(EcResponse::FanRpm { zone, rpm: self.fan_rpm[z] })
+            },
+            EcCommand::SetFanRpm { zone, rpm } => {
+                let z = zone_index(zone)?;
+                self.fan_rpm[z] = rpm;
+                Ok(EcResponse::Done)
+            },
+            EcCommand::GetBoost { target } => {
+                let level = match target {
+                    BoostTarget::Cpu => self.cpu_boost,
+                    BoostTarget::Gpu => self.gpu_boost,
+                };
+                Ok(EcResponse::Boost { target, level })
+            },
+            EcCommand::SetBoost { target: BoostTarget::Cpu, level } => {
+                if level > 3 {
+                    return Err(RazerPacket::RAZER_CMD_FAILURE);
+                }
+                if level > self.limits.max_cpu_boost {
+                    return Err(RazerPacket::RAZER_CMD_NOT_SUPPORTED);
+                }
+                self.cpu_boost = level;
+                Ok(EcResponse::Done)
+            },
+            EcCommand::SetBoost { target: BoostTarget::Gpu, level } => {
+                if level > 2 {
+                    return Err(RazerPacket::RAZER_CMD_FAILURE);
+                }
+                if level > self.limits.max_gpu_boost {
+                    return Err(RazerPacket::RAZER_CMD_NOT_SUPPORTED);
+                }
+                self.gpu_boost = level;
+                Ok(EcResponse::Done)
+            },
+            EcCommand::GetBho => {
+                if !self.has_feature("bho") {
+                    return Err(RazerPacket::RAZER_CMD_NOT_SUPPORTED);
+                }
+                Ok(EcResponse::Bho { is_on: self.bho.0, threshold: self.bho.1 })
+            },
+            EcCommand::SetBho { is_on, threshold } => {
+                if !self.has_feature("bho") {
+                    return Err(RazerPacket::RAZER_CMD_NOT_SUPPORTED);
+                }
+                self.bho = (is_on, threshold);
+                Ok(EcResponse::Done)
+            },
+            EcCommand::GetFirmwareVersion => {
+                Ok(EcResponse::FirmwareVersion { major: SIM_FIRMWARE.0, minor: SIM_FIRMWARE.1 })
+            },
+            EcCommand::GetSerial => Ok(EcResponse::Serial { serial: self.serial }),
+        }
+    }
+}
+
+fn zone_index(zone: u8) -> Result<usize, u8> {
+    match zone {
+        0x01 => Ok(0),
+        0x02 => Ok(1),
+        _ => Err(RazerPacket::RAZER_CMD_FAILURE),
+    }
+}
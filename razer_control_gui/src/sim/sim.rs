@@ -0,0 +1,177 @@
+#[path = "../daemon/packet.rs"]
+mod packet;
+#[path = "../daemon/command.rs"]
+#[allow(dead_code)] // the simulator is the EC, it doesn't encode requests
+mod command;
+mod ec;
+
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use clap::Parser;
+use service::SupportedDevice;
+
+use packet::RazerPacket;
+
+/// Default socket the simulator listens on
+const SIM_SOCKET_PATH: &str = "/tmp/razer-sim-socket";
+
+#[derive(Parser)]
+#[command(version="0.1.0", about="Simulated razer laptop embedded controller", name="razer-sim")]
+struct Cli {
+    /// PID of the laptops.json entry to emulate (e.g. 02b6)
+    pid: String,
+    /// Device file to read the model from, instead of the whole device database
+    #[arg(long)]
+    devices: Option<String>,
+    /// Socket to listen on, point the daemon to it with RAZER_LAPTOP_CONTROL_SIM
+    #[arg(long, default_value = SIM_SOCKET_PATH)]
+    socket: String,
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let (device_file, devices): (String, Vec<SupportedDevice>) = match cli.devices.clone() {
+        Some(device_file) => match service::load_devices(&device_file) {
+            Ok((devices, errors)) => {
+                for error in errors {
+                    eprintln!("Skipping {}", error);
+                }
+                (device_file, devices)
+            },
+            Err(e) => {
+                eprintln!("Error reading {}: {}", device_file, e);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let database = service::DeviceDatabase::load();
+            for error in &database.errors {
+                eprintln!("Skipping {}", error);
+            }
+            ("the device database".to_string(), database.devices)
+        },
+    };
+
+    let device = match devices.iter().find(|d| d.pid.eq_ignore_ascii_case(&cli.pid)) {
+        Some(device) => device,
+        None => {
+            eprintln!("No device with PID {} in {}", cli.pid, device_file);
+            std::process::exit(1);
+        }
+    };
+    let (vid, pid) = match device.ids() {
+        Some(ids) => ids,
+        None => {
+            eprintln!("Invalid VID/PID for {}", device.name);
+            std::process::exit(1);
+        }
+    };
+    println!("Emulating {} ({:04x}:{:04x}), features: {:?}", device.name, vid, pid, device.features);
+
+    // The EC keeps its state across daemon restarts, like the real one
+    let mut ec = ec::SimulatedEc::new(device.features.clone(), device.limits(), &format!("SIM{:04X}000000", pid));
+
+    if std::fs::metadata(&cli.socket).is_ok() {
+        let _ = std::fs::remove_file(&cli.socket);
+    }
+    let listener = match UnixListener::bind(&cli.socket) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not create {}: {}", cli.socket, e);
+            std::process::exit(1);
+        }
+    };
+    println!("Listening on {}", cli.socket);
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => serve(&mut ec, stream, vid, pid),
+            Err(_) => {} // Don't care about this
+        }
+    }
+}
+
+/// The CRC `report` should carry and the one it has, when they differ.
+/// Computed the same way as `RazerPacket::calc_crc`. 0 is what the daemon
+/// sends unless told to fill it in, it is not checked.
+fn crc_mismatch(report: &[u8], request: &RazerPacket) -> Option<(u8, u8)> {
+    let crc = report[2..88].iter().fold(0u8, |acc, b| acc ^ b);
+    if request.crc != 0 && crc != request.crc {
+        return Some((crc, request.crc));
+    }
+    return None;
+}
+
+fn serve(ec: &mut ec::SimulatedEc, mut stream: UnixStream, vid: u16, pid: u16) {
+    println!("Daemon connected");
+    let mut ids = vid.to_le_bytes().to_vec();
+    ids.extend_from_slice(&pid.to_le_bytes());
+    if stream.write_all(&ids).is_err() {
+        return;
+    }
+
+    let mut buf = [0u8; packet::REPORT_SIZE];
+    while stream.read_exact(&mut buf).is_ok() {
+        let request = match bincode::deserialize::<RazerPacket>(&buf) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        };
+
+        if let Some((expected, got)) = crc_mismatch(&buf, &request) {
+            println!("CRC mismatch: expected {:02x}, got {:02x}", expected, got);
+        }
+
+        let response = ec.handle(&request);
+        println!("{:02x}:{:02x} args {:02x?} -> status {:02x} args {:02x?}",
+            request.command_class, request.command_id, &request.args[..4],
+            response.status, &response.args[..4]);
+        match bincode::serialize(&response) {
+            Ok(bytes) => {
+                if stream.write_all(&bytes).is_err() {
+                    break;
+                }
+            },
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+    println!("Daemon disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Vec<u8> {
+        let mut packet = RazerPacket::new(0x0d, 0x02, 0x04);
+        packet.args[..4].copy_from_slice(&[0x00, 0x01, 0x02, 0x00]);
+        return packet.calc_crc();
+    }
+
+    #[test]
+    fn good_report_has_matching_crc() {
+        let report = report();
+        let request: RazerPacket = bincode::deserialize(&report).unwrap();
+        assert_eq!(crc_mismatch(&report, &request), None);
+    }
+
+    #[test]
+    fn report_without_crc_is_not_checked() {
+        let mut packet = RazerPacket::new(0x0d, 0x02, 0x04);
+        packet.args[1] = 0x01;
+        let report = bincode::serialize(&packet).unwrap();
+        assert_eq!(crc_mismatch(&report, &packet), None);
+    }
+
+    #[test]
+    fn corrupted_report_is_a_mismatch() {
+        let mut report = report();
+        report[10] ^= 0xff;
+        let request: RazerPacket = bincode::deserialize(&report).unwrap();
+        assert!(crc_mismatch(&report, &request).is_some());
+    }
+}